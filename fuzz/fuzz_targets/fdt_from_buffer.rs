@@ -0,0 +1,50 @@
+//! Feeds arbitrary blobs to `Fdt::from_buffer` and walks every accepted tree,
+//! asserting that no input makes the FDT parser panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mercuros_mercurius::fdt::{Fdt, Node};
+
+fuzz_target!(|data: &[u8]| {
+    let fdt = match Fdt::from_buffer(data) {
+        Ok(fdt) => fdt,
+        Err(_) => return,
+    };
+
+    for _ in fdt.memory_reservations() {}
+    for _ in fdt.tokens() {}
+
+    for node in fdt.nodes() {
+        visit(&node);
+    }
+
+    if let Some(root) = fdt.root() {
+        for child in root.children() {
+            let _ = child.parent();
+        }
+    }
+
+    let _ = fdt.stdout();
+    let _ = fdt.find_node("/soc/serial@10010000");
+    let _ = fdt.find_node("serial0/child");
+});
+
+fn visit(node: &Node<'_, '_>) {
+    for property in node.properties() {
+        let _ = property.as_u32();
+        let _ = property.as_u64();
+        let _ = property.as_str();
+        for _ in property.strings() {}
+    }
+
+    let _ = node.is_enabled();
+    let _ = node.is_compatible("ns16550a");
+
+    if let Some(reg) = node.reg() {
+        for _ in reg {}
+    }
+    if let Some(regions) = node.regions() {
+        for _ in regions {}
+    }
+}
@@ -0,0 +1,324 @@
+//! Hart enumeration from the `/cpus` device tree node.
+
+#[cfg(test)]
+#[path = "cpus_tests.rs"]
+mod cpus_tests;
+
+use bitflags::bitflags;
+
+use crate::fdt::{Fdt, Node, NodeIterator};
+
+bitflags! {
+    /// RISC-V ISA extensions.
+    pub struct Extensions: u64 {
+        const I = 1 << 0;
+        const M = 1 << 1;
+        const A = 1 << 2;
+        const F = 1 << 3;
+        const D = 1 << 4;
+        const Q = 1 << 5;
+        const C = 1 << 6;
+        const V = 1 << 7;
+        const H = 1 << 8;
+
+        const ZICSR = 1 << 16;
+        const ZIFENCEI = 1 << 17;
+        const ZICNTR = 1 << 18;
+        const ZIHPM = 1 << 19;
+        const ZIHINTPAUSE = 1 << 20;
+        const ZICBOM = 1 << 21;
+        const ZICBOZ = 1 << 22;
+        const ZICBOP = 1 << 23;
+        const ZBA = 1 << 24;
+        const ZBB = 1 << 25;
+        const ZBC = 1 << 26;
+        const ZBS = 1 << 27;
+        const ZKR = 1 << 28;
+
+        const SSTC = 1 << 32;
+        const SSCOFPMF = 1 << 33;
+        const SVPBMT = 1 << 34;
+        const SVNAPOT = 1 << 35;
+        const SVINVAL = 1 << 36;
+        const SVADU = 1 << 37;
+
+        /// Shorthand for the general purpose ISA, IMAFD_Zicsr_Zifencei.
+        const G = Self::I.bits | Self::M.bits | Self::A.bits | Self::F.bits |
+            Self::D.bits | Self::ZICSR.bits | Self::ZIFENCEI.bits;
+    }
+}
+
+/// Lowercase extension names, as used in `riscv,isa` and
+/// `riscv,isa-extensions`.
+static EXTENSION_NAMES: &[(&str, Extensions)] = &[
+    ("i", Extensions::I),
+    ("m", Extensions::M),
+    ("a", Extensions::A),
+    ("f", Extensions::F),
+    ("d", Extensions::D),
+    ("q", Extensions::Q),
+    ("c", Extensions::C),
+    ("v", Extensions::V),
+    ("h", Extensions::H),
+    ("g", Extensions::G),
+    ("zicsr", Extensions::ZICSR),
+    ("zifencei", Extensions::ZIFENCEI),
+    ("zicntr", Extensions::ZICNTR),
+    ("zihpm", Extensions::ZIHPM),
+    ("zihintpause", Extensions::ZIHINTPAUSE),
+    ("zicbom", Extensions::ZICBOM),
+    ("zicboz", Extensions::ZICBOZ),
+    ("zicbop", Extensions::ZICBOP),
+    ("zba", Extensions::ZBA),
+    ("zbb", Extensions::ZBB),
+    ("zbc", Extensions::ZBC),
+    ("zbs", Extensions::ZBS),
+    ("zkr", Extensions::ZKR),
+    ("sstc", Extensions::SSTC),
+    ("sscofpmf", Extensions::SSCOFPMF),
+    ("svpbmt", Extensions::SVPBMT),
+    ("svnapot", Extensions::SVNAPOT),
+    ("svinval", Extensions::SVINVAL),
+    ("svadu", Extensions::SVADU),
+];
+
+impl Extensions {
+    /// Look up a single extension by name (case insensitive).
+    ///
+    /// Returns `None` for extensions unknown to the kernel.
+    pub fn from_name(name: &str) -> Option<Extensions> {
+        EXTENSION_NAMES.iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, extension)| *extension)
+    }
+
+    /// Collect a set of extensions from a list of names, such as the value of
+    /// `riscv,isa-extensions`. Unknown extensions are ignored.
+    pub fn from_names<'a>(names: impl Iterator<Item = &'a str>) -> Extensions {
+        names
+            .filter_map(Extensions::from_name)
+            .fold(Extensions::empty(), |extensions, extension| extensions | extension)
+    }
+}
+
+/// Base ISA and extensions implemented by a hart.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Isa {
+    pub xlen: u32,
+    pub extensions: Extensions,
+}
+
+impl Isa {
+    /// Parse an ISA string, e.g. `rv64imafdc_zicsr_zifencei_sstc`.
+    ///
+    /// Version numbers (e.g. `rv64i2p1`) are accepted and ignored, as are
+    /// extensions unknown to the kernel. Returns `None` if the string does not
+    /// start with a known base ISA.
+    pub fn parse(isa: &str) -> Option<Isa> {
+        let isa = isa.as_bytes();
+        if isa.len() < 4 || !isa[..2].eq_ignore_ascii_case(b"rv") {
+            return None;
+        }
+
+        let xlen = match &isa[2..4] {
+            b"32" => 32,
+            b"64" => 64,
+            _ => return None,
+        };
+
+        let mut extensions = Extensions::empty();
+        let mut parts = isa[4..].split(|byte| *byte == b'_');
+
+        // single letter extensions, optionally followed by a version number
+        if let Some(single) = parts.next() {
+            let mut letters = single.iter().peekable();
+            while let Some(letter) = letters.next() {
+                // multi-letter extensions must be separated by underscores,
+                // but some firmware omits the first one
+                if matches!(letter.to_ascii_lowercase(), b's' | b'z' | b'x') &&
+                    letters.peek().is_some_and(|next| next.is_ascii_alphabetic())
+                {
+                    let start = single.len() - letters.len() - 1;
+                    extensions |= Isa::parse_multi_letter(&single[start..]);
+                    break;
+                }
+
+                let name = [letter.to_ascii_lowercase()];
+                if let Some(extension) = core::str::from_utf8(&name).ok()
+                    .and_then(Extensions::from_name)
+                {
+                    extensions |= extension;
+                }
+
+                while letters.peek().is_some_and(|next| is_version(**next)) {
+                    letters.next();
+                }
+            }
+        }
+
+        for multi in parts {
+            extensions |= Isa::parse_multi_letter(multi);
+        }
+
+        Some(Isa { xlen, extensions })
+    }
+
+    fn parse_multi_letter(name: &[u8]) -> Extensions {
+        let name = match core::str::from_utf8(name) {
+            Ok(name) => name,
+            Err(_) => return Extensions::empty(),
+        };
+
+        if let Some(extension) = Extensions::from_name(name) {
+            return extension;
+        }
+
+        // retry without a trailing version number, e.g. "zicsr2p0"
+        Extensions::from_name(name.trim_end_matches(|c: char| is_version(c as u8)))
+            .unwrap_or_else(Extensions::empty)
+    }
+}
+
+fn is_version(byte: u8) -> bool {
+    byte.is_ascii_digit() || byte == b'p'
+}
+
+/// Virtual memory scheme supported by a hart, from `mmu-type`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MmuType {
+    Bare,
+    Sv32,
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl MmuType {
+    fn parse(mmu_type: &str) -> Option<MmuType> {
+        match mmu_type {
+            "riscv,none" => Some(MmuType::Bare),
+            "riscv,sv32" => Some(MmuType::Sv32),
+            "riscv,sv39" => Some(MmuType::Sv39),
+            "riscv,sv48" => Some(MmuType::Sv48),
+            "riscv,sv57" => Some(MmuType::Sv57),
+            _ => None,
+        }
+    }
+}
+
+/// Hart descriptor, from a `/cpus/cpu@N` node.
+#[derive(Clone, Copy, Debug)]
+pub struct Hart {
+    /// Hart ID, as used by SBI and found in `mhartid`.
+    pub id: u64,
+
+    /// False if the `status` of the node is not `"okay"`.
+    pub enabled: bool,
+
+    /// `None` if the hart has no MMU, or `mmu-type` is missing.
+    pub mmu_type: Option<MmuType>,
+
+    /// Frequency of the `time` CSR in Hz.
+    pub timebase_frequency: Option<u32>,
+
+    /// `None` if the ISA could not be determined.
+    pub isa: Option<Isa>,
+}
+
+impl Hart {
+    /// Build a hart descriptor from a cpu node.
+    ///
+    /// `timebase_frequency` is the value inherited from `/cpus`, which may be
+    /// overridden by the cpu node itself.
+    pub fn from_node(node: &Node<'_, '_>, timebase_frequency: Option<u32>) -> Option<Hart> {
+        let id = node.reg()?.next()?.address;
+
+        let mmu_type = node.property("mmu-type")
+            .and_then(|property| property.as_str())
+            .and_then(MmuType::parse);
+
+        let timebase_frequency = node.property("timebase-frequency")
+            .and_then(|property| property.as_u32())
+            .or(timebase_frequency);
+
+        // riscv,isa-extensions supersedes riscv,isa where present
+        let isa = match (node.property("riscv,isa-base"), node.property("riscv,isa-extensions")) {
+            (Some(base), Some(extensions)) => base.as_str()
+                .and_then(Isa::parse)
+                .map(|isa| Isa {
+                    xlen: isa.xlen,
+                    extensions: isa.extensions | Extensions::from_names(extensions.strings()),
+                }),
+            _ => node.property("riscv,isa")
+                .and_then(|property| property.as_str())
+                .and_then(Isa::parse),
+        };
+
+        Some(Hart {
+            id,
+            enabled: node.is_enabled(),
+            mmu_type,
+            timebase_frequency,
+            isa,
+        })
+    }
+
+    /// Returns true if the kernel can run on this hart.
+    ///
+    /// Harts must be enabled and support paged virtual memory, which rules out
+    /// monitor cores such as the S7 on the FU740.
+    pub fn is_usable(&self) -> bool {
+        self.enabled && matches!(
+            self.mmu_type,
+            Some(MmuType::Sv39) | Some(MmuType::Sv48) | Some(MmuType::Sv57)
+        )
+    }
+
+    /// Returns true if the hart implements all of `extensions`.
+    pub fn has_extensions(&self, extensions: Extensions) -> bool {
+        match self.isa {
+            Some(isa) => isa.extensions.contains(extensions),
+            None => false,
+        }
+    }
+}
+
+/// Iterate over all harts described by the device tree, including harts
+/// which are not usable by the kernel.
+pub fn harts<'a, 'b>(fdt: &'a Fdt<'b>) -> HartIterator<'a, 'b> {
+    let cpus = fdt.find_node("/cpus");
+
+    HartIterator {
+        timebase_frequency: cpus
+            .and_then(|cpus| cpus.property("timebase-frequency"))
+            .and_then(|property| property.as_u32()),
+        children: cpus.map(|cpus| cpus.children()),
+    }
+}
+
+pub struct HartIterator<'a, 'b> {
+    children: Option<NodeIterator<'a, 'b>>,
+    timebase_frequency: Option<u32>,
+}
+
+impl<'a, 'b> core::iter::Iterator for HartIterator<'a, 'b> {
+    type Item = Hart;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let children = self.children.as_mut()?;
+
+        loop {
+            let node = children.next()?;
+
+            let is_cpu = node.property("device_type")
+                .and_then(|property| property.as_str()) == Some("cpu");
+            if !is_cpu {
+                continue;
+            }
+
+            if let Some(hart) = Hart::from_node(&node, self.timebase_frequency) {
+                return Some(hart);
+            }
+        }
+    }
+}
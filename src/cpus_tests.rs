@@ -0,0 +1,110 @@
+use super::*;
+
+static QEMU_VIRT: &[u8] = include_bytes!("../tests/fixtures/qemu-virt.dtb");
+static HIFIVE_UNMATCHED: &[u8] = include_bytes!("../tests/fixtures/hifive-unmatched-a00.dtb");
+
+#[test]
+fn parse_isa_single_letter() {
+    let isa = Isa::parse("rv64imafdc").unwrap();
+
+    assert_eq!(isa.xlen, 64);
+    assert_eq!(
+        isa.extensions,
+        Extensions::I | Extensions::M | Extensions::A | Extensions::F |
+            Extensions::D | Extensions::C
+    );
+}
+
+#[test]
+fn parse_isa_general_purpose() {
+    let isa = Isa::parse("rv64gc").unwrap();
+
+    assert!(isa.extensions.contains(Extensions::G | Extensions::C));
+    assert!(isa.extensions.contains(Extensions::ZICSR | Extensions::ZIFENCEI));
+}
+
+#[test]
+fn parse_isa_multi_letter() {
+    let isa = Isa::parse("rv64imafdcvh_zicsr_zifencei_sstc_svpbmt_xvendor").unwrap();
+
+    assert!(isa.extensions.contains(Extensions::V | Extensions::H));
+    assert!(isa.extensions.contains(Extensions::SSTC | Extensions::SVPBMT));
+    assert!(isa.extensions.contains(Extensions::ZICSR | Extensions::ZIFENCEI));
+    assert!(!isa.extensions.contains(Extensions::SVNAPOT));
+}
+
+#[test]
+fn parse_isa_versions() {
+    let isa = Isa::parse("RV32I2P1M2P0_Zicsr2p0").unwrap();
+
+    assert_eq!(isa.xlen, 32);
+    assert_eq!(isa.extensions, Extensions::I | Extensions::M | Extensions::ZICSR);
+}
+
+#[test]
+fn parse_isa_legacy_mode_letters() {
+    // older QEMU versions append the supported privilege modes
+    let isa = Isa::parse("rv64imafdcsu").unwrap();
+
+    assert!(isa.extensions.contains(Extensions::G - Extensions::ZICSR - Extensions::ZIFENCEI));
+    assert!(isa.extensions.contains(Extensions::C));
+}
+
+#[test]
+fn parse_isa_invalid() {
+    assert!(Isa::parse("").is_none());
+    assert!(Isa::parse("rv").is_none());
+    assert!(Isa::parse("rv128i").is_none());
+    assert!(Isa::parse("x86_64").is_none());
+}
+
+#[test]
+fn extensions_from_names() {
+    let names = ["i", "m", "a", "zicsr", "sstc", "unknown"];
+
+    assert_eq!(
+        Extensions::from_names(names.iter().copied()),
+        Extensions::I | Extensions::M | Extensions::A | Extensions::ZICSR | Extensions::SSTC
+    );
+}
+
+#[test]
+fn qemu_virt_harts() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let harts: Vec<_> = harts(&fdt).collect();
+
+    assert_eq!(harts.len(), 2);
+    for (id, hart) in harts.iter().enumerate() {
+        assert_eq!(hart.id, id as u64);
+        assert!(hart.is_usable());
+        assert_eq!(hart.mmu_type, Some(MmuType::Sv48));
+        assert_eq!(hart.timebase_frequency, Some(10_000_000));
+        assert!(hart.has_extensions(Extensions::I | Extensions::M | Extensions::C));
+    }
+}
+
+#[test]
+fn hifive_unmatched_skips_monitor_core() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+    let harts: Vec<_> = harts(&fdt).collect();
+
+    assert_eq!(harts.len(), 5);
+
+    let monitor = &harts[0];
+    assert_eq!(monitor.id, 0);
+    assert!(!monitor.enabled);
+    assert_eq!(monitor.mmu_type, None);
+    assert!(!monitor.is_usable());
+    assert!(!monitor.has_extensions(Extensions::F));
+
+    let usable: Vec<_> = harts.iter()
+        .filter(|hart| hart.is_usable())
+        .map(|hart| hart.id)
+        .collect();
+    assert_eq!(usable, [1, 2, 3, 4]);
+
+    assert_eq!(harts[1].mmu_type, Some(MmuType::Sv39));
+    assert_eq!(harts[1].timebase_frequency, Some(1_000_000));
+    assert!(harts[1].has_extensions(Extensions::D));
+    assert!(!harts[1].has_extensions(Extensions::V));
+}
@@ -0,0 +1,70 @@
+// Driver binding is tested without probing, as probes create references to
+// device registers which are not mapped on the host.
+
+use super::*;
+
+static QEMU_VIRT: &[u8] = include_bytes!("../../tests/fixtures/qemu-virt.dtb");
+static HIFIVE_UNMATCHED: &[u8] = include_bytes!("../../tests/fixtures/hifive-unmatched-a00.dtb");
+static DRIVERS: &[u8] = include_bytes!("../../tests/fixtures/drivers.dtb");
+
+/// Names of all nodes `probe_all` would probe, and the driver for each.
+fn bindings(fdt: &Fdt<'_>) -> Vec<(String, &'static str)> {
+    fdt.nodes()
+        .filter_map(|node| {
+            let driver = select_driver(&node)?;
+            Some((String::from_utf8(node.name().to_vec()).unwrap(), driver.name))
+        })
+        .collect()
+}
+
+#[test]
+fn qemu_virt_bindings() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+
+    assert_eq!(bindings(&fdt), [("uart@10000000".to_string(), "ns16550a")]);
+}
+
+#[test]
+fn hifive_unmatched_bindings() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+
+    assert_eq!(bindings(&fdt), [
+        ("serial@10010000".to_string(), "fu740-c000-uart"),
+        ("serial@10011000".to_string(), "fu740-c000-uart"),
+    ]);
+}
+
+#[test]
+fn disabled_nodes_are_skipped() {
+    let fdt = Fdt::from_buffer(DRIVERS).unwrap();
+    let disabled = fdt.find_node("/soc/serial@10001000").unwrap();
+
+    // the driver matches, but the node must not be bound
+    assert_eq!(find_driver(&disabled).unwrap().name, "ns16550a");
+    assert!(select_driver(&disabled).is_none());
+    assert_eq!(bindings(&fdt), [
+        ("serial@10000000".to_string(), "ns16550a"),
+        ("serial@10002000".to_string(), "fu740-c000-uart"),
+    ]);
+}
+
+#[test]
+fn later_compatible_values_match() {
+    let fdt = Fdt::from_buffer(DRIVERS).unwrap();
+    let node = fdt.find_node("/soc/serial@10002000").unwrap();
+
+    assert_eq!(find_driver(&node).unwrap().name, "fu740-c000-uart");
+}
+
+#[test]
+fn unknown_compatible_has_no_driver() {
+    let fdt = Fdt::from_buffer(DRIVERS).unwrap();
+
+    let unknown = fdt.find_node("/soc/serial@10003000").unwrap();
+    assert!(find_driver(&unknown).is_none());
+    assert!(select_driver(&unknown).is_none());
+
+    // neither do nodes like buses, which no driver claims
+    let soc = fdt.find_node("/soc").unwrap();
+    assert!(find_driver(&soc).is_none());
+}
@@ -1 +1,83 @@
+//! Device drivers and device tree driver binding.
+
+#[cfg(test)]
+#[path = "drivers_tests.rs"]
+mod drivers_tests;
+
 pub mod uart;
+
+use crate::fdt::{Fdt, Node};
+
+/// Device instantiated by a driver probe.
+pub enum Device {
+    Uart(&'static mut (dyn uart::Uart + Send)),
+}
+
+/// Driver which can be bound to device tree nodes.
+pub struct Driver {
+    pub name: &'static str,
+
+    /// Values of the `compatible` property this driver supports.
+    pub compatible: &'static [&'static str],
+
+    /// Instantiate the driver for a matching device tree node.
+    pub probe: fn(&Node<'_, '_>) -> Option<Device>,
+}
+
+/// All drivers known to the kernel.
+static DRIVERS: &[&Driver] = &[
+    &uart::fu740_c000::DRIVER,
+    &uart::ns16550a::DRIVER,
+];
+
+/// Find the driver best matching the `compatible` property of `node`.
+///
+/// The `compatible` property lists the most specific value first, so the
+/// earliest entry for which a driver exists wins.
+pub fn find_driver(node: &Node<'_, '_>) -> Option<&'static Driver> {
+    node.property("compatible")?
+        .strings()
+        .find_map(|compatible| {
+            DRIVERS.iter()
+                .find(|driver| driver.compatible.contains(&compatible))
+                .copied()
+        })
+}
+
+/// Driver to be bound to a device tree node, or `None` if the node is
+/// disabled or has no matching driver.
+pub fn select_driver(node: &Node<'_, '_>) -> Option<&'static Driver> {
+    if !node.is_enabled() {
+        return None;
+    }
+
+    find_driver(node)
+}
+
+/// Instantiate the driver for a single device tree node.
+///
+/// Returns `None` if the node is disabled, has no matching driver, or the
+/// driver failed to probe the device.
+pub fn probe(node: &Node<'_, '_>) -> Option<Device> {
+    (select_driver(node)?.probe)(node)
+}
+
+/// Walk the device tree and instantiate drivers for all enabled nodes.
+///
+/// Every device successfully probed is handed to `bind`, together with the
+/// node describing it. Returns the number of devices probed.
+pub fn probe_all<F>(fdt: &Fdt<'_>, mut bind: F) -> usize
+where
+    F: FnMut(&Node<'_, '_>, Device)
+{
+    let mut count = 0;
+
+    for node in fdt.nodes() {
+        if let Some(device) = probe(&node) {
+            bind(&node, device);
+            count += 1;
+        }
+    }
+
+    count
+}
@@ -1,11 +1,17 @@
 //! UART driver for HiFive Freedom Unmatched (FU740-C000).
 
+#[cfg(test)]
+#[path = "fu740_c000_tests.rs"]
+mod fu740_c000_tests;
+
 use bitflags::bitflags;
 
-use crate::memory::Register;
+use crate::drivers::{Device, Driver};
+use crate::fdt::Node;
+use crate::memory::{PhysAddr, Register, VirtAddr, phys_to_virt};
 use crate::io::{Io, ReadOnly};
 
-use super::{StopBits, Uart};
+use super::{StopBits, Uart, UartConfig};
 
 // Memory Map
 // 0x00 - txdata
@@ -71,19 +77,28 @@ bitflags! {
     }
 }
 
+pub static DRIVER: Driver = Driver {
+    name: "fu740-c000-uart",
+    compatible: &["sifive,fu740-c000-uart", "sifive,uart0"],
+    probe,
+};
+
+fn probe(node: &Node<'_, '_>) -> Option<Device> {
+    let registers = node.regions()?.next()?;
+
+    // SAFETY: the device tree describes this region as the UART registers,
+    // and is reachable through the direct map.
+    let uart = unsafe {
+        UartFu740::new(phys_to_virt(PhysAddr::new(registers.address)))
+    };
+
+    Some(Device::Uart(uart))
+}
+
 impl UartFu740 {
     // The unsafeness here depends on platform and virtual memory layout
-    pub unsafe fn new(instance: usize) -> Option<&'static mut UartFu740> {
-        // 2 UART instances
-        // 0 - 0x1001_0000 - 0x1001_0FFF
-        // 1 - 0x1001_1000 - 0x1001_1FFF
-        let base: usize = match instance {
-            0 => 0x1001_0000,
-            1 => 0x1001_1000,
-            _ => return None,
-        };
-
-        Some(&mut *(base as *mut Self))
+    pub unsafe fn new(base_address: VirtAddr) -> &'static mut UartFu740 {
+        &mut *base_address.as_mut_ptr::<Self>()
     }
 
     pub fn receive(&mut self) -> Option<u8> {
@@ -105,10 +120,18 @@ impl UartFu740 {
         self.txctrl.write(flags.bits);
     }
 
+    /// Program the baud rate divisor for `baud_rate`, given the frequency of
+    /// the input clock.
+    pub fn set_baud_rate(&mut self, baud_rate: u32, clock_frequency: u32) {
+        // f_baud = f_in / (div + 1)
+        let divisor = (clock_frequency / baud_rate).max(1) - 1;
+        self.div.write(divisor);
+    }
+
     pub fn rx_enable(&mut self) {
         let mut flags = RxCtrlFlags::from_bits_truncate(self.rxctrl.read());
         flags.insert(RxCtrlFlags::ENABLED);
-        self.txctrl.write(flags.bits);
+        self.rxctrl.write(flags.bits);
     }
 
     pub fn set_tx_watermark(
@@ -149,16 +172,24 @@ impl UartFu740 {
 }
 
 impl Uart for UartFu740 {
-    fn init(&mut self, stop_bits: StopBits) {
+    fn init(&mut self, config: &UartConfig) {
+        // the FU740 UART has no parity support, config.parity is ignored
+        if let (Some(baud_rate), Some(clock_frequency)) =
+            (config.baud_rate, config.clock_frequency)
+        {
+            self.set_baud_rate(baud_rate, clock_frequency);
+        }
+
         // disable interrupts
         self.set_tx_watermark(None);
         self.set_rx_watermark(None);
 
         // enable rx & tx
-        self.tx_enable(stop_bits);
+        self.tx_enable(config.stop_bits);
         self.rx_enable();
     }
 
+    #[cfg(target_arch = "riscv64")]
     fn send(&mut self, data: u8) {
         // Atomic write & OR allows sending with confirmation by
         // simultaneously attempting a send and reading the buffer full flag.
@@ -176,6 +207,16 @@ impl Uart for UartFu740 {
             }
         }
     }
+
+    // Non-atomic fallback, allowing the driver to be built for other
+    // architectures (e.g. host-side tests).
+    #[cfg(not(target_arch = "riscv64"))]
+    fn send(&mut self, data: u8) {
+        let full = DataFlags::FIFO_FULL_OR_EMPTY;
+        while DataFlags::from_bits_truncate(self.txdata.read()).contains(full) {}
+
+        self.txdata.write(data as u32);
+    }
 }
 
 unsafe impl Send for UartFu740 {}
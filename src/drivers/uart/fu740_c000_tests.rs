@@ -0,0 +1,35 @@
+use super::*;
+
+fn new_uart() -> &'static mut UartFu740 {
+    let registers = Box::leak(Box::new([0u32; 7]));
+
+    unsafe { UartFu740::new(VirtAddr::from_ptr(registers.as_mut_ptr())) }
+}
+
+#[test]
+fn rx_enable_writes_rxctrl() {
+    let uart = new_uart();
+    uart.tx_enable(StopBits::TwoStopBits);
+    uart.rx_enable();
+
+    assert_eq!(uart.rxctrl.read(), RxCtrlFlags::ENABLED.bits);
+    assert_eq!(uart.txctrl.read(), (TxCtrlFlags::ENABLED | TxCtrlFlags::TWO_STOP_BITS).bits);
+}
+
+#[test]
+fn init_enables_rx_and_tx() {
+    let uart = new_uart();
+    uart.ie.write((InterruptFlags::TX_WATERMARK | InterruptFlags::RX_WATERMARK).bits);
+
+    uart.init(&UartConfig {
+        baud_rate: Some(115200),
+        clock_frequency: Some(115200 * 10),
+        stop_bits: StopBits::TwoStopBits,
+        ..UartConfig::default()
+    });
+
+    assert_eq!(uart.txctrl.read(), (TxCtrlFlags::ENABLED | TxCtrlFlags::TWO_STOP_BITS).bits);
+    assert_eq!(uart.rxctrl.read(), RxCtrlFlags::ENABLED.bits);
+    assert_eq!(uart.div.read(), 9);
+    assert_eq!(uart.ie.read(), 0);
+}
@@ -1,9 +1,6 @@
 mod uart;
 
-pub use uart::{StopBits, Uart};
+pub use uart::{Parity, StopBits, Uart, UartConfig};
 
-#[cfg(feature = "fu740")]
 pub mod fu740_c000;
-
-#[cfg(feature = "qemu")]
 pub mod ns16550a;
@@ -1,11 +1,17 @@
 //! UART driver for NS16550a (QEMU).
 
+#[cfg(test)]
+#[path = "ns16550a_tests.rs"]
+mod ns16550a_tests;
+
 use bitflags::bitflags;
 
-use crate::memory::Register;
+use crate::drivers::{Device, Driver};
+use crate::fdt::Node;
+use crate::memory::{PhysAddr, Register, VirtAddr, phys_to_virt};
 use crate::io::Io;
 
-use super::{StopBits, Uart};
+use super::{Parity, StopBits, Uart, UartConfig};
 
 // Memory Map
 // 0x00 - RBR (RO) / THR (WO)
@@ -18,11 +24,11 @@ use super::{StopBits, Uart};
 // 0x07 - SCR
 #[repr(packed)]
 pub struct UartNs16550a {
-    // data buffers
+    // data buffers / divisor latch low byte (DLAB = 1)
     rbr_thr: Register<u8>,
 
-    // interrupt enable
-    _ier: Register<u8>,
+    // interrupt enable / divisor latch high byte (DLAB = 1)
+    ier: Register<u8>,
 
     // interrupt ident. / FIFO control
     iir_fcr: Register<u8>,
@@ -53,19 +59,69 @@ bitflags! {
     struct LcrFlags: u8 {
         const WORD_LENGTH_SELECT0 = 0x01;
         const WORD_LENGTH_SELECT1 = 0x02;
+        const TWO_STOP_BITS = 0x04;
+        const PARITY_ENABLE = 0x08;
+        const EVEN_PARITY = 0x10;
+        const DIVISOR_LATCH_ACCESS = 0x80;
     }
 }
 
+pub static DRIVER: Driver = Driver {
+    name: "ns16550a",
+    compatible: &["ns16550a"],
+    probe,
+};
+
+fn probe(node: &Node<'_, '_>) -> Option<Device> {
+    let registers = node.regions()?.next()?;
+
+    // SAFETY: the device tree describes this region as the UART registers,
+    // and is reachable through the direct map.
+    let uart = unsafe {
+        UartNs16550a::new(phys_to_virt(PhysAddr::new(registers.address)))
+    };
+
+    Some(Device::Uart(uart))
+}
+
 impl UartNs16550a {
     // The unsafeness here depends on platform and virtual memory layout
-    pub unsafe fn new(base_address: *const core::ffi::c_void) -> &'static mut UartNs16550a {
-        &mut *(base_address as *mut Self)
+    pub unsafe fn new(base_address: VirtAddr) -> &'static mut UartNs16550a {
+        &mut *base_address.as_mut_ptr::<Self>()
     }
 
     pub fn set_word_length(&mut self, _length: usize) {
         // TODO: adjustable word length
-        let lcr = (LcrFlags::WORD_LENGTH_SELECT0 | LcrFlags::WORD_LENGTH_SELECT1).bits;
-        self.lcr.write(lcr);
+        let mut flags = LcrFlags::from_bits_truncate(self.lcr.read());
+        flags.insert(LcrFlags::WORD_LENGTH_SELECT0 | LcrFlags::WORD_LENGTH_SELECT1);
+        self.lcr.write(flags.bits);
+    }
+
+    pub fn set_stop_bits(&mut self, stop_bits: StopBits) {
+        let mut flags = LcrFlags::from_bits_truncate(self.lcr.read());
+        flags.set(LcrFlags::TWO_STOP_BITS, stop_bits == StopBits::TwoStopBits);
+        self.lcr.write(flags.bits);
+    }
+
+    pub fn set_parity(&mut self, parity: Parity) {
+        let mut flags = LcrFlags::from_bits_truncate(self.lcr.read());
+        flags.set(LcrFlags::PARITY_ENABLE, parity != Parity::None);
+        flags.set(LcrFlags::EVEN_PARITY, parity == Parity::Even);
+        self.lcr.write(flags.bits);
+    }
+
+    /// Program the baud rate divisor for `baud_rate`, given the frequency of
+    /// the input clock.
+    pub fn set_baud_rate(&mut self, baud_rate: u32, clock_frequency: u32) {
+        let divisor = baud_divisor(baud_rate, clock_frequency);
+
+        let flags = LcrFlags::from_bits_truncate(self.lcr.read());
+        self.lcr.write((flags | LcrFlags::DIVISOR_LATCH_ACCESS).bits);
+
+        self.rbr_thr.write(divisor as u8);
+        self.ier.write((divisor >> 8) as u8);
+
+        self.lcr.write((flags - LcrFlags::DIVISOR_LATCH_ACCESS).bits);
     }
 
     pub fn fifo_enable(&mut self) {
@@ -74,11 +130,26 @@ impl UartNs16550a {
     }
 }
 
+/// Divisor latch value for `baud_rate`, given the frequency of the input
+/// clock, clamped to what the 16 bit latch can hold.
+///
+/// The UART samples at 16 times the baud rate, so
+/// `f_baud = f_in / (16 * divisor)`.
+fn baud_divisor(baud_rate: u32, clock_frequency: u32) -> u16 {
+    (clock_frequency / baud_rate / 16).clamp(1, 0xFFFF) as u16
+}
+
 impl Uart for UartNs16550a {
-    fn init(&mut self, stop_bits: StopBits) {
-        // TODO: Set stop bits
+    fn init(&mut self, config: &UartConfig) {
+        if let (Some(baud_rate), Some(clock_frequency)) =
+            (config.baud_rate, config.clock_frequency)
+        {
+            self.set_baud_rate(baud_rate, clock_frequency);
+        }
 
         self.set_word_length(8);
+        self.set_stop_bits(config.stop_bits);
+        self.set_parity(config.parity);
 
         // enable rx & tx
         self.fifo_enable();
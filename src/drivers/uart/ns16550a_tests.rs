@@ -0,0 +1,37 @@
+use super::*;
+
+fn new_uart() -> &'static mut UartNs16550a {
+    let registers = Box::leak(Box::new([0u8; 8]));
+
+    unsafe { UartNs16550a::new(VirtAddr::from_ptr(registers.as_mut_ptr())) }
+}
+
+#[test]
+fn baud_divisors() {
+    assert_eq!(baud_divisor(115200, 1_843_200), 1);
+    assert_eq!(baud_divisor(9600, 1_843_200), 12);
+    assert_eq!(baud_divisor(115200, 3_686_400), 2);
+
+    // rounded down, towards the faster rate
+    assert_eq!(baud_divisor(115200, 10_000_000), 5);
+}
+
+#[test]
+fn baud_divisors_are_clamped() {
+    assert_eq!(baud_divisor(230400, 1_843_200), 1);
+    assert_eq!(baud_divisor(50, 100_000_000), 0xFFFF);
+}
+
+#[test]
+fn set_baud_rate_writes_divisor_latch() {
+    let uart = new_uart();
+    uart.lcr.write((LcrFlags::WORD_LENGTH_SELECT0 | LcrFlags::WORD_LENGTH_SELECT1).bits);
+
+    uart.set_baud_rate(9600, 0x0102 * 16 * 9600);
+
+    assert_eq!(uart.rbr_thr.read(), 0x02);
+    assert_eq!(uart.ier.read(), 0x01);
+
+    // the latch is closed again, leaving the line settings alone
+    assert_eq!(uart.lcr.read(), (LcrFlags::WORD_LENGTH_SELECT0 | LcrFlags::WORD_LENGTH_SELECT1).bits);
+}
@@ -1,7 +1,11 @@
+#[cfg(test)]
+#[path = "uart_tests.rs"]
+mod uart_tests;
+
 /// Hardware independent UART interface.
 pub trait Uart {
     /// Initialize the UART device.
-    fn init(&mut self, stop_bits: StopBits);
+    fn init(&mut self, config: &UartConfig);
 
     /// Send a byte over UART.
     ///
@@ -17,12 +21,87 @@ pub trait Uart {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum StopBits {
     OneStopBit,
     TwoStopBits,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// UART line configuration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UartConfig {
+    /// Baud rate, or `None` to keep the rate configured by firmware.
+    pub baud_rate: Option<u32>,
+
+    /// Frequency of the UART input clock in Hz, needed to program the baud
+    /// rate divisor.
+    pub clock_frequency: Option<u32>,
+
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl UartConfig {
+    /// Parse a console options string of the form `<baud>{<parity>{<bits>}}`,
+    /// e.g. `115200n8`, as found in the `stdout-path` of `/chosen`.
+    ///
+    /// Only 8 data bits are supported. The format has no field for stop bits,
+    /// so one stop bit is always used. Returns `None` for malformed or
+    /// unsupported options, rather than a partially applied configuration.
+    pub fn parse(options: &str) -> Option<Self> {
+        let mut config = UartConfig::default();
+
+        let digits = options.find(|c: char| !c.is_ascii_digit()).unwrap_or(options.len());
+        let (baud_rate, options) = options.split_at(digits);
+        if !baud_rate.is_empty() {
+            config.baud_rate = Some(baud_rate.parse().ok().filter(|rate| *rate != 0)?);
+        }
+
+        let mut options = options.chars();
+        config.parity = match options.next() {
+            Some('n') | None => Parity::None,
+            Some('o') => Parity::Odd,
+            Some('e') => Parity::Even,
+            Some(_) => return None,
+        };
+
+        match options.next() {
+            Some('8') | None => {},
+            Some(_) => return None,
+        }
+
+        // flow control ('r' for RTS/CTS) is not supported and ignored
+        match options.next() {
+            Some('r') | None => {},
+            Some(_) => return None,
+        }
+
+        if options.next().is_some() {
+            return None;
+        }
+
+        Some(config)
+    }
+}
+
+impl Default for UartConfig {
+    fn default() -> Self {
+        UartConfig {
+            baud_rate: None,
+            clock_frequency: None,
+            parity: Parity::None,
+            stop_bits: StopBits::OneStopBit,
+        }
+    }
+}
+
 impl core::fmt::Write for &mut dyn Uart {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         Uart::write(*self, s);
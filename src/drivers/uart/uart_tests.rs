@@ -0,0 +1,45 @@
+use super::*;
+
+fn config(baud_rate: Option<u32>, parity: Parity) -> UartConfig {
+    UartConfig { baud_rate, parity, ..UartConfig::default() }
+}
+
+#[test]
+fn parse_full_options() {
+    assert_eq!(UartConfig::parse("115200n8"), Some(config(Some(115200), Parity::None)));
+    assert_eq!(UartConfig::parse("57600o8"), Some(config(Some(57600), Parity::Odd)));
+    assert_eq!(UartConfig::parse("115200e8r"), Some(config(Some(115200), Parity::Even)));
+}
+
+#[test]
+fn parse_partial_options() {
+    assert_eq!(UartConfig::parse("115200"), Some(config(Some(115200), Parity::None)));
+    assert_eq!(UartConfig::parse("9600e"), Some(config(Some(9600), Parity::Even)));
+
+    // no options keeps the baud rate configured by firmware
+    assert_eq!(UartConfig::parse(""), Some(UartConfig::default()));
+}
+
+#[test]
+fn parse_always_uses_one_stop_bit() {
+    let config = UartConfig::parse("115200n8").unwrap();
+
+    assert_eq!(config.stop_bits, StopBits::OneStopBit);
+    assert_eq!(config.clock_frequency, None);
+}
+
+#[test]
+fn parse_unsupported_data_bits() {
+    assert_eq!(UartConfig::parse("9600e7"), None);
+    assert_eq!(UartConfig::parse("115200n5"), None);
+}
+
+#[test]
+fn parse_invalid() {
+    assert_eq!(UartConfig::parse("abc"), None);
+    assert_eq!(UartConfig::parse("115200x9"), None);
+    assert_eq!(UartConfig::parse("115200n8x"), None);
+    assert_eq!(UartConfig::parse("115200n8rr"), None);
+    assert_eq!(UartConfig::parse("0"), None);
+    assert_eq!(UartConfig::parse("99999999999n8"), None);
+}
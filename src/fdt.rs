@@ -2,13 +2,26 @@
 //!
 //! The DeviceTree specification is available at https://www.devicetree.org.
 
-use crate::util::raw_cast;
+#[cfg(test)]
+#[path = "fdt_tests.rs"]
+mod fdt_tests;
 
 // Note: FDT data is stored in big endian format
 
 static MAGIC: u32 = 0xd00dfeed;
 static COMPATIBLE_VERSION: u32 = 17;
 
+// Defaults for #address-cells and #size-cells if the properties are missing
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+// Maximum nesting depth of nodes accepted when parsing
+const MAX_DEPTH: usize = 64;
+
+// Memory reservation entries consist of a 64-bit address and a 64-bit size
+const MEMORY_RESERVATION_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FdtError {
     BadMagic(u32),
     InvalidFormat,
@@ -17,7 +30,7 @@ pub enum FdtError {
 }
 
 pub struct Fdt<'a> {
-    header: &'a Header,
+    header: Header,
     mem_rsvmap: &'a [u8],
     dt_struct: &'a [u8],
     dt_strings: &'a [u8],
@@ -32,68 +45,318 @@ impl<'a> Fdt<'a> {
     }
 
     pub unsafe fn read_length(pointer: *const core::ffi::c_void) -> Result<usize, FdtError> {
-        let header = & *(pointer as *const Header);
+        let header = core::ptr::read_unaligned(pointer as *const Header);
 
         if !header.is_valid() {
-            Err(FdtError::BadMagic(header.magic))
+            Err(FdtError::BadMagic(u32::from_be(header.magic)))
         } else {
             Ok(u32::from_be(header.totalsize) as usize)
         }
     }
 
-    pub unsafe fn from_buffer(buffer: &'a [u8]) -> Result<Fdt<'a>, FdtError> {
+    /// Parse an FDT blob.
+    ///
+    /// The whole blob is validated up front, so that a truncated or corrupt
+    /// blob is rejected here rather than surfacing during later traversal.
+    pub fn from_buffer(buffer: &'a [u8]) -> Result<Fdt<'a>, FdtError> {
         if buffer.len() < core::mem::size_of::<Header>() {
             return Err(FdtError::BufferOverflow);
         }
 
-        let header_buffer = &buffer[0..core::mem::size_of::<Header>()];
-        if let Some(header) = raw_cast::<Header>(header_buffer) {
-            if !header.is_valid() {
-                return Err(FdtError::BadMagic(header.magic));
-            }
-            if !header.is_compatible() {
-                return Err(FdtError::IncompatibleVersion);
-            }
+        // SAFETY: the buffer is large enough to hold the header, and Header
+        // consists of plain integers valid for any bit pattern.
+        let header = unsafe {
+            core::ptr::read_unaligned(buffer.as_ptr() as *const Header)
+        };
+
+        if !header.is_valid() {
+            return Err(FdtError::BadMagic(u32::from_be(header.magic)));
+        }
+        if !header.is_compatible() {
+            return Err(FdtError::IncompatibleVersion);
+        }
 
-            let offset_reservation = u32::from_be(header.off_mem_rsvmap) as usize;
-            let offset_structure = u32::from_be(header.off_dt_struct) as usize;
-            let offset_strings = u32::from_be(header.off_dt_strings) as usize;
+        let total_size = u32::from_be(header.totalsize) as usize;
+        if total_size > buffer.len() {
+            return Err(FdtError::BufferOverflow);
+        }
+        let buffer = &buffer[..total_size];
 
-            let end_structure = offset_structure + u32::from_be(header.size_dt_struct) as usize;
-            let end_strings = offset_strings + u32::from_be(header.size_dt_strings) as usize;
+        let offset_reservation = u32::from_be(header.off_mem_rsvmap) as usize;
+        let offset_structure = u32::from_be(header.off_dt_struct) as usize;
+        let offset_strings = u32::from_be(header.off_dt_strings) as usize;
 
-            // TODO: size of memory reservation map?
+        let end_structure = offset_structure
+            .checked_add(u32::from_be(header.size_dt_struct) as usize)
+            .ok_or(FdtError::InvalidFormat)?;
+        let end_strings = offset_strings
+            .checked_add(u32::from_be(header.size_dt_strings) as usize)
+            .ok_or(FdtError::InvalidFormat)?;
 
-            if offset_structure > end_structure ||
-                offset_strings > end_strings
-            {
-                return Err(FdtError::InvalidFormat);
-            }
+        if !offset_reservation.is_multiple_of(8) || !offset_structure.is_multiple_of(4) {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        if end_structure > buffer.len() || end_strings > buffer.len() {
+            return Err(FdtError::BufferOverflow);
+        }
 
-            if end_strings > buffer.len() {
-                return Err(FdtError::BufferOverflow);
+        // The memory reservation map has no size field in the header, it is
+        // terminated by an entry with both address and size set to zero.
+        let mut end_reservation = offset_reservation;
+        loop {
+            let entry_end = end_reservation + MEMORY_RESERVATION_SIZE;
+            match buffer.get(end_reservation..entry_end) {
+                Some(entry) if entry.iter().all(|byte| *byte == 0) => break,
+                Some(_) => end_reservation = entry_end,
+                None => return Err(FdtError::BufferOverflow),
             }
+        }
+
+        let fdt = Fdt {
+            header,
+            mem_rsvmap: &buffer[offset_reservation..end_reservation],
+            dt_struct: &buffer[offset_structure..end_structure],
+            dt_strings: &buffer[offset_strings..end_strings],
+        };
+
+        fdt.validate_structure()?;
+
+        Ok(fdt)
+    }
+
+    /// Size of the whole blob in bytes, as given by the header.
+    pub fn total_size(&self) -> usize {
+        u32::from_be(self.header.totalsize) as usize
+    }
 
-            Ok(Fdt {
-                header,
-                mem_rsvmap: &buffer[offset_reservation..offset_reservation],
-                dt_struct: &buffer[offset_structure..end_structure],
-                dt_strings: &buffer[offset_strings..end_strings],
-            })
+    /// Iterate over the entries of the memory reservation map.
+    pub fn memory_reservations(&self) -> MemoryReservationIterator<'a> {
+        MemoryReservationIterator {
+            entries: self.mem_rsvmap.chunks_exact(MEMORY_RESERVATION_SIZE),
+        }
+    }
+
+    /// Returns the root node of the device tree.
+    pub fn root(&self) -> Option<Node<'_, 'a>> {
+        let offset = self.skip_nops(0);
+        match self.read_token(offset).ok()?? {
+            (FdtToken::BeginNode(name), _) => Some(Node { fdt: self, offset, name }),
+            _ => None,
+        }
+    }
+
+    /// Find a node by its path, e.g. `/soc/serial@10010000`.
+    ///
+    /// Paths not starting with `/` begin with an alias, which is resolved
+    /// through the `/aliases` node, e.g. `serial0` or `serial0/child`.
+    ///
+    /// Unit addresses may be omitted where they are not needed to tell
+    /// sibling nodes apart.
+    pub fn find_node(&self, path: &str) -> Option<Node<'_, 'a>> {
+        let (mut node, relative_path) = if path.starts_with('/') {
+            (self.root()?, path)
         } else {
-            Err(FdtError::InvalidFormat)
+            let (alias, relative_path) = match path.find('/') {
+                Some(index) => path.split_at(index),
+                None => (path, ""),
+            };
+
+            let alias_path = self.alias(alias)?;
+            if !alias_path.starts_with('/') {
+                return None;
+            }
+
+            (self.find_node(alias_path)?, relative_path)
+        };
+
+        for component in relative_path.split('/').filter(|component| !component.is_empty()) {
+            node = node.child(component)?;
+        }
+
+        Some(node)
+    }
+
+    /// Look up the path an alias refers to in the `/aliases` node.
+    pub fn alias(&self, alias: &str) -> Option<&'a str> {
+        self.root()?
+            .child("aliases")?
+            .property(alias)?
+            .as_str()
+    }
+
+    /// Find the node for the boot console, as selected by the `stdout-path`
+    /// property of `/chosen`.
+    ///
+    /// Returns the node along with the options suffix of the path (if any),
+    /// e.g. `115200n8` for a `stdout-path` of `serial0:115200n8`.
+    pub fn stdout(&self) -> Option<(Node<'_, 'a>, Option<&'a str>)> {
+        let chosen = self.root()?.child("chosen")?;
+        let stdout_path = chosen.property("stdout-path")
+            .or_else(|| chosen.property("linux,stdout-path"))?
+            .as_str()?;
+
+        let (path, options) = match stdout_path.find(':') {
+            Some(index) => (&stdout_path[..index], Some(&stdout_path[(index + 1)..])),
+            None => (stdout_path, None),
+        };
+
+        Some((self.find_node(path)?, options))
+    }
+
+    /// Location of the initial ramdisk loaded by the bootloader, as the range
+    /// `(start, end)` given by `linux,initrd-start` and `linux,initrd-end`
+    /// in `/chosen`.
+    pub fn initrd(&self) -> Option<(u64, u64)> {
+        let chosen = self.root()?.child("chosen")?;
+        let address = |name| {
+            let property = chosen.property(name)?;
+            property.as_u64().or_else(|| property.as_u32().map(u64::from))
+        };
+
+        Some((address("linux,initrd-start")?, address("linux,initrd-end")?))
+    }
+
+    /// Iterate over all nodes of the device tree in depth-first order.
+    pub fn nodes(&self) -> TreeIterator<'_, 'a> {
+        TreeIterator {
+            tokens: self.tokens(),
         }
     }
 
-    pub fn nodes(&self) -> DeviceTreeIterator<'_, 'a> {
+    /// Find the first node which is compatible with `compatible`.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'_, 'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// Iterate over the tokens of the structure block.
+    pub fn tokens(&self) -> DeviceTreeIterator<'_, 'a> {
         DeviceTreeIterator {
             fdt: self,
             next_token_offset: 0,
         }
     }
+
+    /// Check that the structure block is a single, properly nested tree
+    /// terminated by `FDT_END`.
+    fn validate_structure(&self) -> Result<(), FdtError> {
+        let mut offset = 0;
+        let mut depth = 0usize;
+        let mut root_seen = false;
+        // properties must precede any child nodes
+        let mut properties_allowed = false;
+
+        while let Some((token, next_offset)) = self.read_token(offset)? {
+            match token {
+                FdtToken::BeginNode(_) => {
+                    if depth == 0 && root_seen {
+                        return Err(FdtError::InvalidFormat);
+                    }
+
+                    depth += 1;
+                    if depth > MAX_DEPTH {
+                        return Err(FdtError::InvalidFormat);
+                    }
+
+                    root_seen = true;
+                    properties_allowed = true;
+                },
+                FdtToken::EndNode => {
+                    if depth == 0 {
+                        return Err(FdtError::InvalidFormat);
+                    }
+
+                    depth -= 1;
+                    properties_allowed = false;
+                },
+                FdtToken::Property(_) => {
+                    if !properties_allowed {
+                        return Err(FdtError::InvalidFormat);
+                    }
+                },
+            }
+
+            offset = next_offset;
+        }
+
+        if !root_seen || depth != 0 {
+            return Err(FdtError::InvalidFormat);
+        }
+
+        Ok(())
+    }
+
+    /// Read the token at `offset` in the structure block, skipping any
+    /// `FDT_NOP` tokens.
+    ///
+    /// Returns the token together with the offset of the following token, or
+    /// `None` when the `FDT_END` token is reached.
+    fn read_token(&self, offset: usize) -> Result<Option<(FdtToken<'a>, usize)>, FdtError> {
+        let dt_struct = self.dt_struct;
+        let offset = self.skip_nops(offset);
+
+        let mut next_offset = offset + core::mem::size_of::<FdtTokenType>();
+        let token_type = read_word(
+            dt_struct.get(offset..next_offset).ok_or(FdtError::BufferOverflow)?
+        );
+
+        match token_type {
+            FDT_BEGIN_NODE => {
+                let name_start = next_offset;
+                let name_length = dt_struct.get(name_start..)
+                    .and_then(|tail| tail.iter().position(|byte| *byte == 0))
+                    .ok_or(FdtError::BufferOverflow)?;
+
+                next_offset = name_start + name_length;
+                let name = &dt_struct[name_start..next_offset];
+
+                next_offset += 1;
+
+                Ok(Some((FdtToken::BeginNode(name), next_aligned(next_offset))))
+            },
+            FDT_PROP => {
+                let header = dt_struct.get(next_offset..(next_offset + 8))
+                    .ok_or(FdtError::BufferOverflow)?;
+                let length = read_word(&header[0..4]) as usize;
+                let name_offset = read_word(&header[4..8]) as usize;
+
+                let value_start = next_offset + 8;
+                next_offset = value_start.checked_add(length)
+                    .ok_or(FdtError::BufferOverflow)?;
+
+                let value = dt_struct.get(value_start..next_offset)
+                    .ok_or(FdtError::BufferOverflow)?;
+                let name = read_string(self.dt_strings, name_offset)
+                    .ok_or(FdtError::InvalidFormat)?;
+
+                Ok(Some((
+                    FdtToken::Property(Property { name, value }),
+                    next_aligned(next_offset),
+                )))
+            },
+            FDT_END_NODE => Ok(Some((FdtToken::EndNode, next_offset))),
+            FDT_END => Ok(None),
+            _ => Err(FdtError::InvalidFormat),
+        }
+    }
+
+    /// Returns the offset of the first token at or after `offset` which is not
+    /// an `FDT_NOP` token.
+    fn skip_nops(&self, mut offset: usize) -> usize {
+        while let Some(word) = self.dt_struct.get(offset..(offset + 4)) {
+            if read_word(word) != FDT_NOP {
+                break;
+            }
+
+            offset += 4;
+        }
+
+        offset
+    }
 }
 
 /// FDT Header
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Header {
     magic: u32,
@@ -119,6 +382,30 @@ impl Header {
     }
 }
 
+/// Physical memory region reserved by the `/memreserve/` map.
+#[derive(Clone, Copy)]
+pub struct MemoryReservation {
+    pub address: u64,
+    pub size: u64,
+}
+
+pub struct MemoryReservationIterator<'a> {
+    entries: core::slice::ChunksExact<'a, u8>,
+}
+
+impl<'a> core::iter::Iterator for MemoryReservationIterator<'a> {
+    type Item = MemoryReservation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+
+        Some(MemoryReservation {
+            address: read_dword(&entry[0..8]),
+            size: read_dword(&entry[8..16]),
+        })
+    }
+}
+
 pub type FdtTokenType = u32;
 pub const FDT_BEGIN_NODE: FdtTokenType = 0x00000001;
 pub const FDT_END_NODE: FdtTokenType = 0x00000002;
@@ -126,66 +413,431 @@ pub const FDT_PROP: FdtTokenType = 0x00000003;
 pub const FDT_NOP: FdtTokenType = 0x00000004;
 pub const FDT_END: FdtTokenType = 0x00000009;
 
+/// A single token of the FDT structure block.
+#[derive(Clone, Copy)]
+pub enum FdtToken<'a> {
+    /// Start of a node, carrying the node name (including any unit address).
+    BeginNode(&'a [u8]),
+    /// End of the most recently opened node.
+    EndNode,
+    /// Property belonging to the most recently opened node.
+    Property(Property<'a>),
+}
+
+/// Device tree property, borrowed from the FDT buffer.
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    name: &'a [u8],
+    value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Property name, as found in the strings block (without terminator).
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// Raw property value.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// Interpret the value as a single big endian `<u32>`.
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.value.len() != 4 {
+            return None;
+        }
+
+        Some(read_word(self.value))
+    }
+
+    /// Interpret the value as a single big endian `<u64>`.
+    pub fn as_u64(&self) -> Option<u64> {
+        if self.value.len() != 8 {
+            return None;
+        }
+
+        Some(read_dword(self.value))
+    }
+
+    /// Iterate over the strings of a string list value, e.g. `compatible`.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> {
+        let value = match self.value.split_last() {
+            Some((0, value)) => value,
+            _ => &[],
+        };
+
+        value
+            .split(|byte| *byte == 0)
+            .filter_map(|string| core::str::from_utf8(string).ok())
+            .filter(|string| !string.is_empty())
+    }
+
+    /// Interpret the value as a single null terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.value.split_last() {
+            Some((0, string)) => core::str::from_utf8(string).ok(),
+            _ => None,
+        }
+    }
+}
+
 pub struct DeviceTreeIterator<'a, 'b> {
     fdt: &'a Fdt<'b>,
     next_token_offset: usize,
 }
 
 impl<'a, 'b> core::iter::Iterator for DeviceTreeIterator<'a, 'b> {
-    type Item = Option<&'b [u8]>;
+    type Item = FdtToken<'b>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let dt_struct = self.fdt.dt_struct;
-        let offset = self.next_token_offset;
+        // the structure block has been validated, so errors can not occur here
+        let (token, next_offset) = self.fdt.read_token(self.next_token_offset).ok()??;
+        self.next_token_offset = next_offset;
+
+        Some(token)
+    }
+}
+
+/// Device tree node, referring to its `FDT_BEGIN_NODE` token in the
+/// structure block.
+#[derive(Clone, Copy)]
+pub struct Node<'a, 'b> {
+    fdt: &'a Fdt<'b>,
+    offset: usize,
+    name: &'b [u8],
+}
+
+impl<'a, 'b> Node<'a, 'b> {
+    /// Node name, including the unit address (if any).
+    pub fn name(&self) -> &'b [u8] {
+        self.name
+    }
+
+    /// Iterate over the properties of this node.
+    pub fn properties(&self) -> PropertyIterator<'a, 'b> {
+        PropertyIterator {
+            tokens: self.inner_tokens(),
+        }
+    }
+
+    /// Look up a property of this node by name.
+    pub fn property(&self, name: &str) -> Option<Property<'b>> {
+        self.properties().find(|property| property.name() == name.as_bytes())
+    }
+
+    /// Iterate over the direct children of this node.
+    pub fn children(&self) -> NodeIterator<'a, 'b> {
+        NodeIterator {
+            tokens: self.inner_tokens(),
+            done: false,
+        }
+    }
+
+    /// Look up a direct child of this node by name.
+    ///
+    /// The unit address may be omitted from `name`, in which case the first
+    /// child with a matching base name is returned.
+    pub fn child(&self, name: &str) -> Option<Node<'a, 'b>> {
+        let name = name.as_bytes();
+        self.children().find(|child| {
+            child.name == name || (
+                !name.contains(&b'@') &&
+                    child.name.split(|byte| *byte == b'@').next() == Some(name)
+            )
+        })
+    }
+
+    /// Find the parent of this node.
+    ///
+    /// The structure block does not record parent links, so this walks down
+    /// from the root node and is linear in the size of the tree.
+    pub fn parent(&self) -> Option<Node<'a, 'b>> {
+        let mut current = self.fdt.root()?;
+
+        'descend: loop {
+            let mut children = current.children();
+            while let Some(child) = children.next() {
+                if child.offset == self.offset {
+                    return Some(current);
+                }
+
+                // children.tokens is now positioned right after the subtree
+                // of child, which tells us whether self is contained in it.
+                if self.offset > child.offset &&
+                    self.offset < children.tokens.next_token_offset
+                {
+                    current = child;
+                    continue 'descend;
+                }
+            }
 
-        if offset >= dt_struct.len() {
             return None;
         }
+    }
 
-        let mut next_offset = offset + core::mem::size_of::<FdtTokenType>();
-        let token_type = read_word(&dt_struct[offset..next_offset]);
+    /// Returns true if `compatible` is listed in the `compatible` property.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        match self.property("compatible") {
+            Some(property) => property.strings().any(|string| string == compatible),
+            None => false,
+        }
+    }
 
-        match token_type {
-            FDT_BEGIN_NODE => {
-                let name_start = next_offset;
-                while dt_struct[next_offset] != 0 {
-                    next_offset += 1;
-                    if next_offset >= dt_struct.len() {
-                        return None;
-                    }
+    /// Returns true unless the `status` property marks the node as unusable.
+    ///
+    /// A missing `status` property is equivalent to `"okay"`.
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status") {
+            Some(property) => matches!(property.as_str(), Some("okay") | Some("ok")),
+            None => true,
+        }
+    }
+
+    /// Number of cells used for addresses in the `reg` property of children.
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|property| property.as_u32())
+            .unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    /// Number of cells used for sizes in the `reg` property of children.
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|property| property.as_u32())
+            .unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+
+    /// Iterate over the `reg` entries of this node, in the address space of
+    /// the parent bus.
+    pub fn reg(&self) -> Option<RegIterator<'b>> {
+        let parent = self.parent()?;
+        let reg = self.property("reg")?;
+
+        RegIterator::new(reg.value(), parent.address_cells(), parent.size_cells())
+    }
+
+    /// Iterate over the `reg` entries of this node, translated to CPU physical
+    /// addresses through the `ranges` of all ancestor buses.
+    ///
+    /// Entries which can not be translated are skipped.
+    pub fn regions(&self) -> Option<RegionIterator<'a, 'b>> {
+        Some(RegionIterator {
+            bus: self.parent()?,
+            reg: self.reg()?,
+        })
+    }
+
+    /// Translate `address` from the child address space of this node to a CPU
+    /// physical address.
+    ///
+    /// Returns `None` if any bus along the way lacks a `ranges` property or
+    /// has no mapping covering the address.
+    pub fn translate_address(&self, mut address: u64) -> Option<u64> {
+        let mut bus = *self;
+
+        while let Some(parent) = bus.parent() {
+            let ranges = bus.property("ranges")?.value();
+
+            // an empty ranges property denotes an identity mapping
+            if !ranges.is_empty() {
+                let child_cells = bus.address_cells();
+                let parent_cells = parent.address_cells();
+                let size_cells = bus.size_cells();
+
+                // addresses and sizes wider than 64 bits are not supported
+                if child_cells > 2 || parent_cells > 2 || size_cells > 2 {
+                    return None;
                 }
 
-                let name = &dt_struct[name_start..next_offset];
+                let entry_size = (child_cells + parent_cells + size_cells) as usize * 4;
+                if entry_size == 0 {
+                    return None;
+                }
 
-                next_offset += 1;
-                self.next_token_offset = next_aligned(next_offset);
+                address = ranges.chunks_exact(entry_size).find_map(|entry| {
+                    let (child, entry) = entry.split_at(child_cells as usize * 4);
+                    let (parent, size) = entry.split_at(parent_cells as usize * 4);
 
-                Some(Some(name))
-            },
-            FDT_PROP => {
-                let length = read_word(&dt_struct[next_offset..(next_offset + 4)]) as usize;
-                let _name_offset = read_word(
-                    &dt_struct[(next_offset + 4)..(next_offset + 8)]
-                );
+                    let child = read_cells(child, child_cells)?;
+                    let parent = read_cells(parent, parent_cells)?;
+                    let size = read_cells(size, size_cells)?;
 
-                next_offset = next_offset + 8 + length;
-                self.next_token_offset = next_aligned(next_offset);
+                    if address >= child && address - child < size {
+                        parent.checked_add(address - child)
+                    } else {
+                        None
+                    }
+                })?;
+            }
 
-                Some(None)
-            },
-            FDT_END_NODE => {
-                self.next_token_offset = next_offset;
-                Some(None)
-            },
-            FDT_NOP => {
-                self.next_token_offset = next_offset;
-                Some(None)
-            },
+            bus = parent;
+        }
+
+        Some(address)
+    }
+
+    /// Tokens following the `FDT_BEGIN_NODE` token of this node.
+    fn inner_tokens(&self) -> DeviceTreeIterator<'a, 'b> {
+        let next_token_offset = match self.fdt.read_token(self.offset) {
+            Ok(Some((_, next_offset))) => next_offset,
+            _ => self.fdt.dt_struct.len(),
+        };
+
+        DeviceTreeIterator {
+            fdt: self.fdt,
+            next_token_offset,
+        }
+    }
+}
+
+pub struct PropertyIterator<'a, 'b> {
+    tokens: DeviceTreeIterator<'a, 'b>,
+}
+
+impl<'a, 'b> core::iter::Iterator for PropertyIterator<'a, 'b> {
+    type Item = Property<'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // properties always precede child nodes
+        match self.tokens.next()? {
+            FdtToken::Property(property) => Some(property),
             _ => None,
         }
     }
 }
 
+pub struct NodeIterator<'a, 'b> {
+    tokens: DeviceTreeIterator<'a, 'b>,
+    done: bool,
+}
+
+impl<'a, 'b> core::iter::Iterator for NodeIterator<'a, 'b> {
+    type Item = Node<'a, 'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let offset = self.tokens.fdt.skip_nops(self.tokens.next_token_offset);
+            match self.tokens.next() {
+                Some(FdtToken::Property(_)) => continue,
+                Some(FdtToken::BeginNode(name)) => {
+                    // skip over the subtree of the child
+                    let mut depth = 1usize;
+                    while depth > 0 {
+                        match self.tokens.next() {
+                            Some(FdtToken::BeginNode(_)) => depth += 1,
+                            Some(FdtToken::EndNode) => depth -= 1,
+                            Some(FdtToken::Property(_)) => {},
+                            None => {
+                                self.done = true;
+                                break;
+                            },
+                        }
+                    }
+
+                    return Some(Node {
+                        fdt: self.tokens.fdt,
+                        offset,
+                        name,
+                    });
+                },
+                Some(FdtToken::EndNode) | None => {
+                    self.done = true;
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+/// Single `reg` entry.
+#[derive(Clone, Copy)]
+pub struct RegEntry {
+    pub address: u64,
+    pub size: u64,
+}
+
+pub struct RegIterator<'a> {
+    entries: core::slice::ChunksExact<'a, u8>,
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> RegIterator<'a> {
+    fn new(value: &'a [u8], address_cells: u32, size_cells: u32) -> Option<Self> {
+        // addresses and sizes wider than 64 bits are not supported
+        if address_cells == 0 || address_cells > 2 || size_cells > 2 {
+            return None;
+        }
+
+        let entry_size = (address_cells + size_cells) as usize * 4;
+
+        Some(RegIterator {
+            entries: value.chunks_exact(entry_size),
+            address_cells,
+            size_cells,
+        })
+    }
+}
+
+impl<'a> core::iter::Iterator for RegIterator<'a> {
+    type Item = RegEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        let (address, size) = entry.split_at(self.address_cells as usize * 4);
+
+        Some(RegEntry {
+            address: read_cells(address, self.address_cells)?,
+            size: read_cells(size, self.size_cells)?,
+        })
+    }
+}
+
+pub struct RegionIterator<'a, 'b> {
+    bus: Node<'a, 'b>,
+    reg: RegIterator<'b>,
+}
+
+impl<'a, 'b> core::iter::Iterator for RegionIterator<'a, 'b> {
+    type Item = RegEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.reg.next()?;
+
+            if let Some(address) = self.bus.translate_address(entry.address) {
+                return Some(RegEntry { address, size: entry.size });
+            }
+        }
+    }
+}
+
+pub struct TreeIterator<'a, 'b> {
+    tokens: DeviceTreeIterator<'a, 'b>,
+}
+
+impl<'a, 'b> core::iter::Iterator for TreeIterator<'a, 'b> {
+    type Item = Node<'a, 'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let offset = self.tokens.fdt.skip_nops(self.tokens.next_token_offset);
+            if let FdtToken::BeginNode(name) = self.tokens.next()? {
+                return Some(Node {
+                    fdt: self.tokens.fdt,
+                    offset,
+                    name,
+                });
+            }
+        }
+    }
+}
+
 fn read_word(buffer: &[u8]) -> u32 {
     let mut bytes = [0u8; 4];
     bytes.copy_from_slice(buffer);
@@ -193,6 +845,31 @@ fn read_word(buffer: &[u8]) -> u32 {
     u32::from_be_bytes(bytes)
 }
 
+fn read_dword(buffer: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(buffer);
+
+    u64::from_be_bytes(bytes)
+}
+
+/// Read a big endian value of one or two cells.
+fn read_cells(buffer: &[u8], cells: u32) -> Option<u64> {
+    match cells {
+        0 => Some(0),
+        1 => Some(read_word(buffer) as u64),
+        2 => Some(read_dword(buffer)),
+        _ => None,
+    }
+}
+
+/// Read a null terminated string starting at `offset`, excluding the terminator.
+fn read_string(buffer: &[u8], offset: usize) -> Option<&[u8]> {
+    let tail = buffer.get(offset..)?;
+    let length = tail.iter().position(|byte| *byte == 0)?;
+
+    Some(&tail[..length])
+}
+
 fn next_aligned(mut offset: usize) -> usize {
     // jump to next 32-bit aligned boundary
     while offset & 0x3 > 0 {
@@ -0,0 +1,233 @@
+use super::*;
+
+static QEMU_VIRT: &[u8] = include_bytes!("../tests/fixtures/qemu-virt.dtb");
+static HIFIVE_UNMATCHED: &[u8] = include_bytes!("../tests/fixtures/hifive-unmatched-a00.dtb");
+static QEMU_VIRT_OPENSBI: &[u8] = include_bytes!("../tests/fixtures/qemu-virt-opensbi.dtb");
+
+// Header field offsets, for corrupting fixtures
+const OFFSET_MAGIC: usize = 0;
+const OFFSET_TOTALSIZE: usize = 4;
+const OFFSET_DT_STRUCT: usize = 8;
+const OFFSET_VERSION: usize = 20;
+const OFFSET_LAST_COMP_VERSION: usize = 24;
+
+fn write_header_field(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..(offset + 4)].copy_from_slice(&value.to_be_bytes());
+}
+
+fn read_header_field(buffer: &[u8], offset: usize) -> u32 {
+    read_word(&buffer[offset..(offset + 4)])
+}
+
+fn names<'a, 'b: 'a>(nodes: impl Iterator<Item = Node<'a, 'b>>) -> Vec<&'b [u8]> {
+    nodes.map(|node| node.name()).collect()
+}
+
+#[test]
+fn qemu_virt_root_properties() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let root = fdt.root().unwrap();
+
+    assert_eq!(root.name(), b"");
+    assert_eq!(root.property("model").unwrap().as_str(), Some("riscv-virtio,qemu"));
+    assert_eq!(root.property("compatible").unwrap().as_str(), Some("riscv-virtio"));
+    assert_eq!(root.address_cells(), 2);
+    assert_eq!(root.size_cells(), 2);
+    assert!(root.property("missing").is_none());
+}
+
+#[test]
+fn qemu_virt_children() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let root = fdt.root().unwrap();
+
+    assert_eq!(
+        names(root.children()),
+        [
+            &b"fw-cfg@10100000"[..],
+            b"flash@20000000",
+            b"chosen",
+            b"memory@80000000",
+            b"cpus",
+            b"soc",
+        ]
+    );
+
+    let cpus = root.child("cpus").unwrap();
+    assert_eq!(names(cpus.children()), [&b"cpu@0"[..], b"cpu@1", b"cpu-map"]);
+}
+
+#[test]
+fn qemu_virt_uart() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let uart = fdt.find_node("/soc/uart@10000000").unwrap();
+
+    assert!(uart.is_compatible("ns16550a"));
+    assert!(uart.is_enabled());
+    assert_eq!(uart.property("clock-frequency").unwrap().as_u32(), Some(0x384000));
+    assert_eq!(uart.parent().unwrap().name(), b"soc");
+
+    let reg: Vec<_> = uart.regions().unwrap().map(|reg| (reg.address, reg.size)).collect();
+    assert_eq!(reg, [(0x1000_0000, 0x100)]);
+
+    let (stdout, options) = fdt.stdout().unwrap();
+    assert_eq!(stdout.name(), b"uart@10000000");
+    assert_eq!(options, None);
+}
+
+#[test]
+fn qemu_virt_multiple_reg_entries() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let flash = fdt.find_node("/flash").unwrap();
+
+    let reg: Vec<_> = flash.reg().unwrap().map(|reg| (reg.address, reg.size)).collect();
+    assert_eq!(reg, [(0x2000_0000, 0x200_0000), (0x2200_0000, 0x200_0000)]);
+}
+
+#[test]
+fn qemu_virt_string_list() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let test = fdt.find_compatible("sifive,test0").unwrap();
+
+    let compatible: Vec<_> = test.property("compatible").unwrap().strings().collect();
+    assert_eq!(compatible, ["sifive,test1", "sifive,test0", "syscon"]);
+}
+
+#[test]
+fn qemu_virt_no_memory_reservations() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+
+    assert_eq!(fdt.memory_reservations().count(), 0);
+}
+
+#[test]
+fn qemu_virt_opensbi_memory_reservations() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT_OPENSBI).unwrap();
+
+    let reservations: Vec<_> = fdt.memory_reservations()
+        .map(|reservation| (reservation.address, reservation.size))
+        .collect();
+    assert_eq!(reservations, [(0x8700_0000, 0x80_0000)]);
+}
+
+#[test]
+fn hifive_unmatched_stdout_alias() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+
+    assert_eq!(fdt.alias("serial1"), Some("/soc/serial@10011000"));
+
+    let (stdout, options) = fdt.stdout().unwrap();
+    assert_eq!(stdout.name(), b"serial@10010000");
+    assert_eq!(options, None);
+
+    let uart = fdt.find_compatible("sifive,uart0").unwrap();
+    assert_eq!(uart.name(), b"serial@10010000");
+    assert_eq!(uart.regions().unwrap().next().unwrap().address, 0x1001_0000);
+}
+
+#[test]
+fn hifive_unmatched_memory() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+    let memory = fdt.find_node("/memory").unwrap();
+
+    let reg: Vec<_> = memory.regions().unwrap().map(|reg| (reg.address, reg.size)).collect();
+    assert_eq!(reg, [(0x8000_0000, 0x4_0000_0000)]);
+}
+
+#[test]
+fn hifive_unmatched_disabled_nodes() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+
+    assert!(!fdt.find_node("/cpus/cpu@0").unwrap().is_enabled());
+    assert!(fdt.find_node("/cpus/cpu@1").unwrap().is_enabled());
+    assert!(!fdt.find_node("/soc/i2c@10031000").unwrap().is_enabled());
+}
+
+#[test]
+fn hifive_unmatched_untranslatable_reg() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+    let sensor = fdt.find_node("/soc/i2c@10030000/temperature-sensor@4c").unwrap();
+
+    // I2C addresses have no size and no mapping to the CPU address space
+    let reg: Vec<_> = sensor.reg().unwrap().map(|reg| (reg.address, reg.size)).collect();
+    assert_eq!(reg, [(0x4c, 0)]);
+    assert_eq!(sensor.regions().unwrap().count(), 0);
+}
+
+#[test]
+fn hifive_unmatched_parent_chain() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+    let flash = fdt.find_node("/soc/spi@10040000/flash@0").unwrap();
+
+    let spi = flash.parent().unwrap();
+    let soc = spi.parent().unwrap();
+    let root = soc.parent().unwrap();
+
+    assert_eq!(spi.name(), b"spi@10040000");
+    assert_eq!(soc.name(), b"soc");
+    assert_eq!(root.name(), b"");
+    assert!(root.parent().is_none());
+}
+
+#[test]
+fn find_node_not_found() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+
+    assert!(fdt.find_node("/soc/serial@10012000").is_none());
+    assert!(fdt.find_node("/soc/uart").is_none());
+    assert!(fdt.find_node("serial2").is_none());
+    assert!(fdt.find_node("").is_none());
+}
+
+#[test]
+fn nodes_visits_every_node() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+
+    let begin_nodes = fdt.tokens()
+        .filter(|token| matches!(token, FdtToken::BeginNode(_)))
+        .count();
+
+    assert_eq!(fdt.nodes().count(), begin_nodes);
+    assert_eq!(fdt.nodes().filter(|node| node.is_compatible("virtio,mmio")).count(), 8);
+}
+
+#[test]
+fn bad_magic() {
+    let mut buffer = QEMU_VIRT.to_vec();
+    write_header_field(&mut buffer, OFFSET_MAGIC, 0xdeadbeef);
+
+    assert_eq!(Fdt::from_buffer(&buffer).err(), Some(FdtError::BadMagic(0xdeadbeef)));
+}
+
+#[test]
+fn incompatible_version() {
+    let mut buffer = QEMU_VIRT.to_vec();
+    write_header_field(&mut buffer, OFFSET_VERSION, 16);
+
+    assert_eq!(Fdt::from_buffer(&buffer).err(), Some(FdtError::IncompatibleVersion));
+
+    let mut buffer = QEMU_VIRT.to_vec();
+    write_header_field(&mut buffer, OFFSET_LAST_COMP_VERSION, 18);
+
+    assert_eq!(Fdt::from_buffer(&buffer).err(), Some(FdtError::IncompatibleVersion));
+}
+
+#[test]
+fn truncated_buffer() {
+    let total_size = read_header_field(QEMU_VIRT, OFFSET_TOTALSIZE) as usize;
+
+    assert_eq!(
+        Fdt::from_buffer(&QEMU_VIRT[..(total_size - 1)]).err(),
+        Some(FdtError::BufferOverflow)
+    );
+    assert_eq!(Fdt::from_buffer(&QEMU_VIRT[..16]).err(), Some(FdtError::BufferOverflow));
+}
+
+#[test]
+fn invalid_token() {
+    let mut buffer = QEMU_VIRT.to_vec();
+    let offset_structure = read_header_field(&buffer, OFFSET_DT_STRUCT) as usize;
+    write_header_field(&mut buffer, offset_structure, 0x42);
+
+    assert_eq!(Fdt::from_buffer(&buffer).err(), Some(FdtError::InvalidFormat));
+}
@@ -1,10 +1,15 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #![feature(asm)]
 
+extern crate alloc;
+
+pub mod cpus;
 pub mod drivers;
 pub mod fdt;
 pub mod io;
 pub mod memory;
+pub mod relocate;
 pub mod serial;
+pub mod sync;
 pub mod util;
@@ -1,43 +1,66 @@
 #![no_std]
 #![no_main]
+#![feature(alloc_error_handler)]
 
-use core::convert::TryInto;
+use core::alloc::Layout;
 use core::fmt::Write;
+use core::mem::size_of_val;
 use core::panic::PanicInfo;
 
-use mercuros_uefi::{
-    MemoryMap,
-    api::boot_services::memory::{
-        EFI_LOADER_DATA,
-        EFI_BOOT_SERVICES_CODE,
-        EFI_BOOT_SERVICES_DATA,
-        EFI_CONVENTIONAL_MEMORY,
-    },
-};
+use mercuros_uefi::MemoryMap;
 use mercuros_mercurius::{
+    cpus,
+    relocate,
     serial,
-    drivers::uart::Uart,
-    fdt::{Fdt, FdtError},
-    memory::frame::Buddy,
+    fdt::{Fdt, FdtError, Node},
+    memory::{
+        PhysAddr,
+        phys_to_virt,
+        frame::FRAME_ALLOCATOR,
+        heap::KernelHeap,
+        layout::{self, KernelImage},
+        map::{MemoryRegions, Reservation, ReservedRanges},
+    },
 };
 
-const PAGE_SIZE: u64 = 4096;
+/// Stack reserved on either side of the stack pointer at entry, as the extent
+/// of the stack set up by the bootloader is unknown.
+const BOOT_STACK_RESERVE: u64 = 64 * 1024;
+
+#[global_allocator]
+static HEAP: KernelHeap = KernelHeap::new();
 
 #[no_mangle]
 pub extern "C" fn _start(dtb: *const core::ffi::c_void, mmap: *const MemoryMap) -> ! {
+    // SAFETY: nothing has touched any statics yet
+    let unsupported_relocations = unsafe { relocate::relocate_self() };
+
+    let fdt = unsafe { Fdt::from_ptr(dtb) };
+    if let Ok(ref fdt) = fdt {
+        serial::init(fdt);
+    }
+
     serial::WRITER.lock().write_str("Hello World!\r\n").unwrap();
 
-    match unsafe { Fdt::from_ptr(dtb) } {
-        Ok(fdt) => {
+    if unsupported_relocations > 0 {
+        report_relocations();
+    }
+
+    match fdt {
+        Ok(ref fdt) => {
             serial::WRITER.lock().write_str("\r\nFDT:\r\n").unwrap();
-            for node in fdt.nodes() {
-                if let Some(name) = node {
-                    serial::WRITER.lock().write("/");
-                    for byte in name {
-                        serial::WRITER.lock().send(*byte);
-                    }
-                    serial::WRITER.lock().write("\r\n");
-                }
+            if let Some(root) = fdt.root() {
+                print_node(root, 0);
+            }
+
+            serial::WRITER.lock().write_str("\r\nHarts:\r\n").unwrap();
+            for hart in cpus::harts(fdt) {
+                serial::WRITER.lock().write_fmt(format_args!(
+                    "{}: {:?}{}\r\n",
+                    hart.id,
+                    hart.mmu_type,
+                    if hart.is_usable() { "" } else { " (skipped)" }
+                )).unwrap();
             }
         },
         Err(FdtError::IncompatibleVersion) => {
@@ -48,59 +71,187 @@ pub extern "C" fn _start(dtb: *const core::ffi::c_void, mmap: *const MemoryMap)
         },
     };
 
-    // Find the first free memory region and set up a page frame allocation
-    // table there. Mark allocation status of any memory regions falling within
-    // the table coverage.
-    let mut buddy: Option<&'static mut Buddy> = None;
-    for descriptor in unsafe { &*mmap } {
-        match buddy {
-            None => {
-                if descriptor.r#type == EFI_CONVENTIONAL_MEMORY {
-                    // SAFETY: according to the memory map, this region is unoccupied and
-                    // therefore safe to write to.
-                    unsafe {
-                        let raw_buddy = Buddy::new(
-                            descriptor.physical_start,
-                            descriptor.physical_start
-                        );
-
-                        if descriptor.number_of_pages > 1 {
-                            raw_buddy.free(
-                                descriptor.physical_start + PAGE_SIZE,
-                                descriptor.number_of_pages as usize - 1
-                            );
-                        }
-
-                        buddy = Some(raw_buddy);
-                    }
-                }
-            },
-            Some(ref mut buddy) => {
-                // FIXME: EFI_LOADER_DATA regions might contain the kernel
-                if descriptor.r#type == EFI_LOADER_DATA ||
-                    descriptor.r#type == EFI_BOOT_SERVICES_CODE ||
-                    descriptor.r#type == EFI_BOOT_SERVICES_DATA ||
-                    descriptor.r#type == EFI_CONVENTIONAL_MEMORY
-                {
-                    buddy.free(
-                        descriptor.physical_start,
-                        descriptor.number_of_pages.try_into().unwrap()
-                    );
-                }
-            },
-        };
+    // Without UEFI (e.g. QEMU -kernel) the device tree is the only source of
+    // information on physical memory.
+    let mut regions = if !mmap.is_null() {
+        let mut regions = MemoryRegions::from_uefi(unsafe { &*mmap });
+
+        // Regions reserved by the FDT (e.g. firmware) must never be handed
+        // out, regardless of what the memory map claims.
+        if let Ok(ref fdt) = fdt {
+            regions.add_fdt_reservations(fdt);
+        }
+
+        regions
+    } else if let Ok(ref fdt) = fdt {
+        MemoryRegions::from_fdt(fdt)
+    } else {
+        MemoryRegions::new()
+    };
+
+    // The kernel, its stack and anything handed over by the bootloader may
+    // lie in memory reported as usable, e.g. EFI_LOADER_DATA.
+    let image = KernelImage::current();
+    let mut reserved = ReservedRanges::new();
+    reserved.add(Reservation::Kernel, image.start, image.size());
+
+    // physical memory is identity mapped until entering the higher half
+    let stack = PhysAddr::new(&reserved as *const ReservedRanges as u64);
+    reserved.add(Reservation::Stack, stack - BOOT_STACK_RESERVE, 2 * BOOT_STACK_RESERVE);
+
+    if let Ok(ref fdt) = fdt {
+        reserved.add_fdt(fdt, PhysAddr::new(dtb as u64));
+    }
+
+    if !mmap.is_null() {
+        let mmap = unsafe { &*mmap };
+        let address = PhysAddr::new(mmap as *const MemoryMap as u64);
+        reserved.add(Reservation::UefiMemoryMap, address, size_of_val(mmap) as u64);
+
+        // the descriptors may be kept apart from the map itself
+        let descriptors = mmap.into_iter().fold(None, |span, descriptor| {
+            let start = PhysAddr::new(descriptor as *const _ as u64);
+            let end = start + size_of_val(descriptor) as u64;
+
+            match span {
+                Some((span_start, span_end)) => Some((start.min(span_start), end.max(span_end))),
+                None => Some((start, end)),
+            }
+        });
+        if let Some((start, end)) = descriptors {
+            reserved.add(Reservation::UefiMemoryMap, start, end - start);
+        }
+    }
+
+    serial::WRITER.lock().write_str("\r\nReserved memory:\r\n").unwrap();
+    for range in &reserved {
+        serial::WRITER.lock().write_fmt(format_args!(
+            "{:#018X} - {:#018X}: {:?}\r\n",
+            range.start,
+            range.end,
+            range.reservation
+        )).unwrap();
+    }
+
+    regions.add_reserved(&reserved);
+
+    serial::WRITER.lock().write_str("\r\nMemory map:\r\n").unwrap();
+    for region in &regions {
+        serial::WRITER.lock().write_fmt(format_args!(
+            "{:#018X} - {:#018X}: {:?}\r\n",
+            region.start.start_address(),
+            region.end(),
+            region.kind
+        )).unwrap();
+    }
+
+    if regions.dropped() > 0 {
+        serial::WRITER.lock().write_fmt(format_args!(
+            "Memory map too large, {} region(s) ignored\r\n",
+            regions.dropped()
+        )).unwrap();
+    }
+
+    // Set up page frame allocation tables covering all usable memory. The
+    // tables themselves are placed in the first pages of the memory regions.
+    let mut frames = FRAME_ALLOCATOR.lock();
+    for region in regions.usable() {
+        // SAFETY: according to the memory map, these regions are unoccupied
+        // and therefore safe to write to.
+        unsafe { frames.add_region(region.start, region.pages) };
     }
 
     serial::WRITER.lock().write_str("\r\nAvailable physical memory:\r\n").unwrap();
-    if let Some(ref buddy) = buddy {
-        serial::WRITER.lock().write_fmt(format_args!("{:?}", buddy)).unwrap();
+    serial::WRITER.lock().write_fmt(format_args!("{:?}", *frames)).unwrap();
+    serial::WRITER.lock().write_fmt(format_args!("{}\r\n", frames.stats())).unwrap();
+
+    // Move the kernel into the higher half, with a direct map of everything
+    // below the end of RAM, including the MMIO regions in between.
+    let memory_end = regions.usable().map(|region| region.end()).max().unwrap_or_default();
+    let kernel_space = layout::build_kernel_space(&image, memory_end, &mut frames);
+    drop(frames);
+
+    match kernel_space {
+        // SAFETY: the stack lies in RAM, and nothing on it is used by
+        // kernel_main
+        Ok(space) => unsafe { layout::enter(&space, &image, kernel_main, dtb as usize) },
+        Err(error) => {
+            serial::WRITER.lock().write_fmt(format_args!(
+                "\r\nFailed to build kernel address space: {:?}\r\n",
+                error
+            )).unwrap();
+        },
+    }
+
+    loop {}
+}
+
+/// Kernel entry point in the higher half.
+///
+/// No trap handler is installed yet: `layout::enter` leaves stvec pointing at
+/// a loop halting the hart, so any trap from here on halts it.
+extern "C" fn kernel_main(dtb: usize) -> ! {
+    // SAFETY: statics still point into the image at its load address, and
+    // have not been used since entering the higher half
+    unsafe { relocate::relocate_self() };
+
+    // The console still refers to the UART by its physical address, which is
+    // no longer mapped.
+    let fdt = unsafe { Fdt::from_ptr(phys_to_virt(PhysAddr::new(dtb as u64)).as_ptr()) };
+    if let Ok(ref fdt) = fdt {
+        serial::init(fdt);
     }
 
+    serial::WRITER.lock().write_fmt(format_args!(
+        "\r\nRunning in the higher half at {:#018X}\r\n",
+        kernel_main as *const () as usize
+    )).unwrap();
+
     loop {}
 }
 
+fn report_relocations() {
+    let (_, relocations) = relocate::image_relocations();
+
+    for relocation in relocations.iter().filter(|relocation| !relocation.is_supported()) {
+        serial::WRITER.lock().write_fmt(format_args!(
+            "Unsupported relocation type {} at offset {:#X}\r\n",
+            relocation.kind(),
+            relocation.offset
+        )).unwrap();
+    }
+}
+
+fn print_node(node: Node<'_, '_>, depth: usize) {
+    {
+        let mut writer = serial::WRITER.lock();
+        for _ in 0..depth {
+            writer.write("  ");
+        }
+        writer.write("/");
+        for byte in node.name() {
+            writer.send(*byte);
+        }
+        writer.write("\r\n");
+    }
+
+    for child in node.children() {
+        print_node(child, depth + 1);
+    }
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     serial::WRITER.lock().write_fmt(format_args!("[PANIC] {}\r\n", info)).unwrap();
     loop {}
 }
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    serial::WRITER.lock().write_fmt(format_args!(
+        "[ALLOC ERROR] out of memory allocating {} byte(s), aligned to {}\r\n",
+        layout.size(),
+        layout.align()
+    )).unwrap();
+    loop {}
+}
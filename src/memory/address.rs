@@ -0,0 +1,247 @@
+//! Typed physical and virtual addresses, and the pages and frames built on
+//! them.
+//!
+//! Addresses themselves may hold any value. Pages and frames are always
+//! aligned to their size, so APIs taking them need no alignment checks of
+//! their own.
+
+#[cfg(test)]
+#[path = "address_tests.rs"]
+mod address_tests;
+
+use core::marker::PhantomData;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+macro_rules! address_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[repr(transparent)]
+        pub struct $name(u64);
+
+        impl $name {
+            pub const fn new(address: u64) -> Self {
+                $name(address)
+            }
+
+            pub const fn zero() -> Self {
+                $name(0)
+            }
+
+            pub const fn as_u64(self) -> u64 {
+                self.0
+            }
+
+            /// Returns true if the address is a multiple of `align`, which
+            /// must be a power of two.
+            pub fn is_aligned(self, align: u64) -> bool {
+                self.0 & (align - 1) == 0
+            }
+
+            /// Round down to a multiple of `align`, which must be a power of
+            /// two.
+            pub fn align_down(self, align: u64) -> Self {
+                $name(self.0 & !(align - 1))
+            }
+
+            /// Round up to a multiple of `align`, which must be a power of
+            /// two.
+            pub fn align_up(self, align: u64) -> Self {
+                $name(self.0.wrapping_add(align - 1) & !(align - 1))
+            }
+        }
+
+        impl Add<u64> for $name {
+            type Output = Self;
+
+            fn add(self, offset: u64) -> Self {
+                $name(self.0 + offset)
+            }
+        }
+
+        impl AddAssign<u64> for $name {
+            fn add_assign(&mut self, offset: u64) {
+                self.0 += offset;
+            }
+        }
+
+        impl Sub<u64> for $name {
+            type Output = Self;
+
+            fn sub(self, offset: u64) -> Self {
+                $name(self.0 - offset)
+            }
+        }
+
+        impl SubAssign<u64> for $name {
+            fn sub_assign(&mut self, offset: u64) {
+                self.0 -= offset;
+            }
+        }
+
+        /// Distance in bytes between two addresses.
+        impl Sub for $name {
+            type Output = u64;
+
+            fn sub(self, other: Self) -> u64 {
+                self.0 - other.0
+            }
+        }
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, concat!(stringify!($name), "({:#018X})"), self.0)
+            }
+        }
+
+        impl core::fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+
+        impl core::fmt::UpperHex for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::UpperHex::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+address_type! {
+    /// Physical memory address.
+    PhysAddr
+}
+
+address_type! {
+    /// Virtual memory address, as seen by the running kernel.
+    VirtAddr
+}
+
+impl VirtAddr {
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        VirtAddr(ptr as u64)
+    }
+
+    pub fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+/// Size of a page or frame, one of `Size4K`, `Size2M` or `Size1G`.
+pub trait PageSize: Copy + Eq + Ord + core::fmt::Debug {
+    const SIZE: u64;
+}
+
+/// 4 KiB base page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size4K {}
+
+/// 2 MiB megapage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size2M {}
+
+/// 1 GiB gigapage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size1G {}
+
+impl PageSize for Size4K {
+    const SIZE: u64 = 1 << 12;
+}
+
+impl PageSize for Size2M {
+    const SIZE: u64 = 1 << 21;
+}
+
+impl PageSize for Size1G {
+    const SIZE: u64 = 1 << 30;
+}
+
+/// Page of virtual memory, aligned to its size.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page<S: PageSize = Size4K> {
+    start: VirtAddr,
+    size: PhantomData<S>,
+}
+
+impl<S: PageSize> Page<S> {
+    pub const SIZE: u64 = S::SIZE;
+
+    /// Page starting at `address`, or None if it is not aligned to the page
+    /// size.
+    pub fn from_start_address(address: VirtAddr) -> Option<Self> {
+        if !address.is_aligned(S::SIZE) {
+            return None;
+        }
+
+        Some(Page { start: address, size: PhantomData })
+    }
+
+    pub fn containing_address(address: VirtAddr) -> Self {
+        Page { start: address.align_down(S::SIZE), size: PhantomData }
+    }
+
+    pub fn start_address(self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn size(self) -> u64 {
+        S::SIZE
+    }
+}
+
+impl<S: PageSize> core::fmt::Debug for Page<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Page({:#018X}, {:#X})", self.start, S::SIZE)
+    }
+}
+
+/// Frame of physical memory, aligned to its size.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Frame<S: PageSize = Size4K> {
+    start: PhysAddr,
+    size: PhantomData<S>,
+}
+
+impl<S: PageSize> Frame<S> {
+    pub const SIZE: u64 = S::SIZE;
+
+    /// Frame starting at `address`, or None if it is not aligned to the
+    /// frame size.
+    pub fn from_start_address(address: PhysAddr) -> Option<Self> {
+        if !address.is_aligned(S::SIZE) {
+            return None;
+        }
+
+        Some(Frame { start: address, size: PhantomData })
+    }
+
+    pub fn containing_address(address: PhysAddr) -> Self {
+        Frame { start: address.align_down(S::SIZE), size: PhantomData }
+    }
+
+    pub fn start_address(self) -> PhysAddr {
+        self.start
+    }
+
+    pub fn size(self) -> u64 {
+        S::SIZE
+    }
+}
+
+impl Frame {
+    /// Frame at physical address zero, e.g. for initializing arrays.
+    pub const fn zero() -> Self {
+        Frame { start: PhysAddr::zero(), size: PhantomData }
+    }
+}
+
+impl<S: PageSize> core::fmt::Debug for Frame<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Frame({:#018X}, {:#X})", self.start, S::SIZE)
+    }
+}
@@ -0,0 +1,60 @@
+use super::*;
+
+#[test]
+fn address_alignment() {
+    let address = PhysAddr::new(0x8020_1234);
+
+    assert!(!address.is_aligned(0x1000));
+    assert!(address.is_aligned(4));
+    assert_eq!(address.align_down(0x1000), PhysAddr::new(0x8020_1000));
+    assert_eq!(address.align_up(0x1000), PhysAddr::new(0x8020_2000));
+    assert_eq!(address.align_up(0x20_0000), PhysAddr::new(0x8040_0000));
+    assert_eq!(PhysAddr::new(0x8020_0000).align_up(0x20_0000), PhysAddr::new(0x8020_0000));
+}
+
+#[test]
+fn address_arithmetic() {
+    let mut address = VirtAddr::new(0xFFFF_FFC0_0000_0000);
+
+    address += 0x1000;
+    assert_eq!(address, VirtAddr::new(0xFFFF_FFC0_0000_1000));
+    assert_eq!(address - 0x800, VirtAddr::new(0xFFFF_FFC0_0000_0800));
+    assert_eq!(address - VirtAddr::new(0xFFFF_FFC0_0000_0000), 0x1000);
+
+    let value = 0u64;
+    assert_eq!(VirtAddr::from_ptr(&value).as_ptr::<u64>(), &value as *const u64);
+}
+
+#[test]
+fn address_formatting() {
+    let address = PhysAddr::new(0x8000_1000);
+
+    assert_eq!(format!("{:?}", address), "PhysAddr(0x0000000080001000)");
+    assert_eq!(format!("{:#018X}", address), "0x0000000080001000");
+    assert_eq!(format!("{:x}", VirtAddr::new(0xABC)), "abc");
+}
+
+#[test]
+fn pages_are_aligned() {
+    assert_eq!(Page::<Size4K>::from_start_address(VirtAddr::new(0x1234)), None);
+    assert_eq!(Page::<Size2M>::from_start_address(VirtAddr::new(0x1000)), None);
+
+    let page = Page::<Size2M>::from_start_address(VirtAddr::new(0x20_0000)).unwrap();
+    assert_eq!(page.start_address(), VirtAddr::new(0x20_0000));
+    assert_eq!(page.size(), 0x20_0000);
+
+    let page = Page::<Size1G>::containing_address(VirtAddr::new(0x5234_5678));
+    assert_eq!(page.start_address(), VirtAddr::new(0x4000_0000));
+}
+
+#[test]
+fn frames_are_aligned() {
+    assert_eq!(Frame::<Size4K>::from_start_address(PhysAddr::new(0x8000_0800)), None);
+    assert_eq!(Frame::<Size1G>::from_start_address(PhysAddr::new(0x8020_0000)), None);
+
+    let frame: Frame = Frame::containing_address(PhysAddr::new(0x8000_1234));
+    assert_eq!(frame.start_address(), PhysAddr::new(0x8000_1000));
+    assert_eq!(frame.size(), 4096);
+    assert_eq!(Frame::<Size2M>::SIZE, 0x20_0000);
+    assert_eq!(format!("{:?}", frame), "Frame(0x0000000080001000, 0x1000)");
+}
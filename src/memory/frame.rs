@@ -6,79 +6,818 @@ mod frame_tests;
 
 use core::ptr::addr_of_mut;
 
+use crate::sync::IrqMutex;
+
+use super::{Frame, PAGE_SIZE, PhysAddr, VirtAddr, phys_to_virt};
+
 const FREE: u8 = 0u8;
 const USED: u8 = 1u8;
 
-// 256 bytes covering 1 page per bit, i.e. 8 pages per byte
-const PAGE_COUNT: usize = 256 * 8;
+/// Size of the memory range covered by a single map.
+const MAP_SIZE: u64 = Buddy::PAGE_COUNT as u64 * PAGE_SIZE;
 
-#[repr(C)]
-pub struct Buddy {
-    l0: [u8; 256],
-    l1: [u8; 128],
-    l2: [u8; 64],
-    l3: [u8; 32],
-    base: u64,
-    next_map: *const Buddy,
+/// Reasons for rejecting a free, carrying the address of the first offending
+/// page.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FreeError {
+    /// The page is already free.
+    DoubleFree(PhysAddr),
+    /// The page is not covered by the allocator.
+    OutOfRange(PhysAddr),
+    /// The page is in use, but was never handed out by `allocate`.
+    NotAllocated(PhysAddr),
 }
 
-impl Buddy {
-    /// Initializes a buddy-map for a memory region starting at `base`,
-    /// and places the map in memory at `address`.
+impl core::fmt::Display for FreeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FreeError::DoubleFree(address) =>
+                write!(f, "double free of page {:#018X}", address),
+            FreeError::OutOfRange(address) =>
+                write!(f, "free of unmanaged page {:#018X}", address),
+            FreeError::NotAllocated(address) =>
+                write!(f, "free of page {:#018X} which was never allocated", address),
+        }
+    }
+}
+
+/// Range of consecutive free pages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FreeRegion {
+    pub start: PhysAddr,
+    pub pages: usize,
+}
+
+impl FreeRegion {
+    pub fn end(&self) -> PhysAddr {
+        self.start + self.pages as u64 * PAGE_SIZE
+    }
+}
+
+/// Page counts of a map, or of all maps of an allocator.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// Pages covered by the maps, including any never added as free.
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+    /// Size of the largest free region.
+    pub largest_free: usize,
+}
+
+impl FrameStats {
+    fn from_regions<I: Iterator<Item = FreeRegion>>(total: usize, regions: I) -> Self {
+        let mut stats = FrameStats { total, ..FrameStats::default() };
+
+        for region in regions {
+            stats.free += region.pages;
+            stats.largest_free = stats.largest_free.max(region.pages);
+        }
+        stats.used = total - stats.free;
+
+        stats
+    }
+}
+
+/// Compact single line summary, e.g. for boot logs.
+impl core::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} free of {} ({} used), largest free region {}",
+            Pages(self.free),
+            Pages(self.total),
+            Pages(self.used),
+            Pages(self.largest_free)
+        )
+    }
+}
+
+/// Page count formatted as a size, in the largest unit that fits.
+struct Pages(usize);
+
+impl core::fmt::Display for Pages {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let bytes = self.0 as u64 * PAGE_SIZE;
+
+        match bytes {
+            _ if bytes >= 1 << 30 => write!(f, "{} GiB", bytes >> 30),
+            _ if bytes >= 1 << 20 => write!(f, "{} MiB", bytes >> 20),
+            _ => write!(f, "{} KiB", bytes >> 10),
+        }
+    }
+}
+
+/// Iterator merging adjacent free regions.
+struct MergedRegions<I> {
+    regions: I,
+    pending: Option<FreeRegion>,
+}
+
+impl<I: Iterator<Item = FreeRegion>> MergedRegions<I> {
+    fn new(regions: I) -> Self {
+        MergedRegions { regions, pending: None }
+    }
+}
+
+impl<I: Iterator<Item = FreeRegion>> Iterator for MergedRegions<I> {
+    type Item = FreeRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let region = match self.regions.next() {
+                Some(region) => region,
+                None => return self.pending.take(),
+            };
+
+            match self.pending {
+                Some(ref mut pending) if pending.end() == region.start => {
+                    pending.pages += region.pages;
+                },
+                _ => {
+                    if let Some(done) = self.pending.replace(region) {
+                        return Some(done);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Page frame allocator covering any number of memory regions.
+///
+/// Memory is divided into `MAP_SIZE` aligned windows, each tracked by a
+/// `Buddy` map. Maps are created as regions are added, and kept in a list
+/// ordered by base address, linked through `next_map`. Links hold physical
+/// addresses, zero ending the list, and maps are accessed through the direct
+/// map.
+///
+/// Maps with free memory are also kept in one list per order, by the order of
+/// their largest free block, linked through `next_free_map` and likewise
+/// ordered by base address. Allocations only ever visit maps on the lists of
+/// orders large enough to hold them.
+pub struct FrameAllocator {
+    first_map: PhysAddr,
+    free_maps: [PhysAddr; MAX_ORDER + 1],
+    // unused space in the page currently holding maps
+    storage: PhysAddr,
+    storage_left: usize,
+    // panic on invalid frees, rather than only returning an error
+    checked: bool,
+}
+
+impl FrameAllocator {
+    pub const fn new() -> Self {
+        FrameAllocator {
+            first_map: PhysAddr::zero(),
+            free_maps: [PhysAddr::zero(); MAX_ORDER + 1],
+            storage: PhysAddr::zero(),
+            storage_left: 0,
+            checked: false,
+        }
+    }
+
+    /// Like `new`, but any invalid free panics with the offending address.
+    pub const fn new_checked() -> Self {
+        FrameAllocator {
+            checked: true,
+            ..FrameAllocator::new()
+        }
+    }
+
+    /// Add `page_count` free pages starting at `start`.
     ///
-    /// Both `base` and `address` must be page aligned memory addresses.
+    /// Any maps needed to cover the region are placed in pages taken from the
+    /// start of the region itself. Regions too small to hold a new map along
+    /// with at least one free page are skipped.
     ///
-    /// Upon initialization the whole map will be marked as occupied.
-    pub unsafe fn new(base: u64, address: u64) -> &'static mut Self {
-        let ptr = address as *mut Buddy;
+    /// # Safety
+    ///
+    /// The region must be unoccupied, writable, and must not overlap
+    /// previously added regions.
+    pub unsafe fn add_region(&mut self, start: Frame, mut page_count: usize) {
+        let mut page_base = start.start_address();
 
-        // initialize all layers as used
-        addr_of_mut!((*ptr).l0).write_bytes(0xFFu8, 1);
-        addr_of_mut!((*ptr).l1).write_bytes(0xFFu8, 1);
-        addr_of_mut!((*ptr).l2).write_bytes(0xFFu8, 1);
-        addr_of_mut!((*ptr).l3).write_bytes(0xFFu8, 1);
+        while page_count > 0 {
+            let map_base = page_base.align_down(MAP_SIZE);
 
-        addr_of_mut!((*ptr).base).write(base);
-        addr_of_mut!((*ptr).next_map).write(core::ptr::null());
+            if self.map_mut(map_base).is_none() {
+                if self.storage_left < core::mem::size_of::<Buddy>() {
+                    let pages = core::mem::size_of::<Buddy>().div_ceil(PAGE_SIZE as usize);
+
+                    // not worth starting a new map for
+                    if page_count <= pages {
+                        return;
+                    }
+
+                    self.storage = page_base;
+                    self.storage_left = pages * PAGE_SIZE as usize;
+
+                    page_base += pages as u64 * PAGE_SIZE;
+                    page_count -= pages;
+                }
 
-        // SAFETY: all fields of Buddy have been initialized
-        let buddy = &mut *ptr;
+                Buddy::new(Frame::containing_address(map_base), phys_to_virt(self.storage));
+                self.insert_map(self.storage);
 
-        buddy
+                self.storage += core::mem::size_of::<Buddy>() as u64;
+                self.storage_left -= core::mem::size_of::<Buddy>();
+            }
+
+            let count = page_count.min(((map_base + MAP_SIZE - page_base) / PAGE_SIZE) as usize);
+            self.update_map(map_base, |map| map.add_free(Frame::containing_address(page_base), count));
+
+            page_base += count as u64 * PAGE_SIZE;
+            page_count -= count;
+        }
     }
 
     /// Attempt to allocate `page_count` consecutive pages.
-    pub fn allocate(&mut self, page_count: usize) -> Option<*mut core::ffi::c_void> {
-        // TODO
+    pub fn allocate(&mut self, page_count: usize) -> Option<Frame> {
+        self.allocate_constrained(page_count, PAGE_SIZE, PhysAddr::new(u64::MAX))
+    }
+
+    /// Attempt to allocate `page_count` consecutive pages, aligned to `align`
+    /// bytes and ending at or below `max_phys_addr`.
+    ///
+    /// `align` must be a power of two. Maps are searched by the order of their
+    /// largest free block, smallest first, and then in order of base address,
+    /// stopping at the first one starting past `max_phys_addr`. Maps without a
+    /// free block of the requested size are never visited.
+    pub fn allocate_constrained(
+        &mut self,
+        page_count: usize,
+        align: u64,
+        max_phys_addr: PhysAddr,
+    ) -> Option<Frame> {
+        if page_count == 0 || !align.is_power_of_two() {
+            return None;
+        }
+
+        let order = page_count.next_power_of_two().trailing_zeros() as usize;
+
+        for largest_free in order..=MAX_ORDER {
+            let mut map = self.free_maps[largest_free];
 
-        if page_count == 1 {
-            for byte_index in 0..self.l0.len() {
-                if self.l0[byte_index] == 0xFF {
-                    continue;
+            while let Some(buddy) = map_at(map) {
+                if buddy.base > max_phys_addr {
+                    break;
                 }
 
-                for offset in (byte_index * 8)..(byte_index * 8 + 8) {
-                    if self.check_layer(offset, 0) {
-                        let address = self.offset_to_address(offset);
-                        self.mark(address, 1);
-                        return Some(address as *mut core::ffi::c_void);
+                // with alignment beyond the map size, only the first page of a
+                // suitably aligned map will do
+                if align < MAP_SIZE || buddy.base.is_aligned(align) {
+                    if let Some(address) = buddy.allocate_constrained(page_count, align, max_phys_addr) {
+                        self.relink(map, Some(largest_free));
+                        return Some(address);
                     }
                 }
+
+                map = buddy.next_free_map;
             }
         }
 
         None
     }
 
-    /// Mark specific page(s) as free.
+    /// Free page(s) previously handed out by `allocate`.
+    ///
+    /// Nothing is freed unless all of the pages are currently allocated.
+    /// Allocators created with `new_checked` panic instead of returning an
+    /// error.
+    pub fn free(&mut self, start: Frame, page_count: usize) -> Result<(), FreeError> {
+        let page_base = start.start_address();
+        let result = self
+            .for_each_map(page_base, page_count, |map, base, count| map.check_free(base, count))
+            .and_then(|_| {
+                self.for_each_map(page_base, page_count, |map, base, count| map.free(base, count))
+            });
+
+        if let (Err(error), true) = (result, self.checked) {
+            panic!("{}", error);
+        }
+
+        result
+    }
+
+    /// Mark specific page(s) as allocated.
+    ///
+    /// Returns false if some of the pages are not covered by any map.
+    pub fn mark(&mut self, start: Frame, page_count: usize) -> bool {
+        self.for_each_map(start.start_address(), page_count, |map, base, count| {
+            map.mark(base, count);
+            Ok(())
+        }).is_ok()
+    }
+
+    /// Iterate over free memory, merging adjacent blocks across all maps.
+    pub fn free_regions(&self) -> impl Iterator<Item = FreeRegion> + '_ {
+        MergedRegions::new(self.maps().flat_map(|map| map.free_blocks()))
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats::from_regions(self.maps().count() * Buddy::PAGE_COUNT, self.free_regions())
+    }
+
+    /// Iterate over all maps, in order of base address.
+    pub fn maps(&self) -> MapIterator<'_> {
+        MapIterator {
+            next: map_at(self.first_map).map(|map| &*map),
+        }
+    }
+
+    /// Split the page range `[page_base, page_base + page_count)` along map
+    /// boundaries, and call `f` with each part and the map covering it.
+    ///
+    /// All parts are visited, and the first error from `f` or from a part not
+    /// covered by any map is returned.
+    fn for_each_map<F>(
+        &mut self,
+        mut page_base: PhysAddr,
+        mut page_count: usize,
+        mut f: F,
+    ) -> Result<(), FreeError>
+    where
+        F: FnMut(&mut Buddy, Frame, usize) -> Result<(), FreeError>,
+    {
+        let mut result = Ok(());
+
+        while page_count > 0 {
+            let map_base = page_base.align_down(MAP_SIZE);
+            let count = page_count.min(((map_base + MAP_SIZE - page_base) / PAGE_SIZE) as usize);
+
+            let part = self
+                .update_map(map_base, |map| f(map, Frame::containing_address(page_base), count))
+                .unwrap_or(Err(FreeError::OutOfRange(page_base)));
+            result = result.and(part);
+
+            page_base += count as u64 * PAGE_SIZE;
+            page_count -= count;
+        }
+
+        result
+    }
+
+    fn map_mut(&mut self, map_base: PhysAddr) -> Option<&mut Buddy> {
+        map_at(self.find_map(map_base))
+    }
+
+    /// Physical address of the map with the given base, or 0 if there is none.
+    fn find_map(&self, map_base: PhysAddr) -> PhysAddr {
+        let mut map = self.first_map;
+
+        while let Some(buddy) = map_at(map) {
+            if buddy.base >= map_base {
+                return if buddy.base == map_base { map } else { PhysAddr::zero() };
+            }
+
+            map = buddy.next_map;
+        }
+
+        PhysAddr::zero()
+    }
+
+    /// Call `f` with the map at `map_base`, keeping the map on the free map
+    /// list matching its largest free block afterwards.
+    fn update_map<F, R>(&mut self, map_base: PhysAddr, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Buddy) -> R,
+    {
+        let address = self.find_map(map_base);
+        let map = map_at(address)?;
+
+        let largest_free = map.largest_free();
+        let result = f(map);
+        self.relink(address, largest_free);
+
+        Some(result)
+    }
+
+    /// Move the map at the physical `address` from the free map list of
+    /// `old_order` to the one matching its current largest free block.
+    fn relink(&mut self, address: PhysAddr, old_order: Option<usize>) {
+        let new_order = match map_at(address) {
+            Some(map) => map.largest_free(),
+            None => return,
+        };
+        if new_order == old_order {
+            return;
+        }
+
+        if let Some(order) = old_order {
+            let mut link = &mut self.free_maps[order];
+
+            while let Some(buddy) = map_at(*link) {
+                if *link == address {
+                    *link = buddy.next_free_map;
+                    break;
+                }
+
+                link = &mut buddy.next_free_map;
+            }
+        }
+
+        if let Some(order) = new_order {
+            insert_ordered(&mut self.free_maps[order], address, |map| &mut map.next_free_map);
+        }
+    }
+
+    /// Link the map at the physical `address` into the list of maps, keeping
+    /// the list ordered.
+    fn insert_map(&mut self, address: PhysAddr) {
+        insert_ordered(&mut self.first_map, address, |map| &mut map.next_map);
+    }
+}
+
+/// Link the map at the physical `address` into the list starting at `link`,
+/// keeping the list ordered by base address. `next` selects the field linking
+/// the list.
+fn insert_ordered(mut link: &mut PhysAddr, address: PhysAddr, next: fn(&mut Buddy) -> &mut PhysAddr) {
+    let new_map = match map_at(address) {
+        Some(new_map) => new_map,
+        None => return,
+    };
+
+    while let Some(buddy) = map_at(*link) {
+        if buddy.base > new_map.base {
+            break;
+        }
+
+        link = next(buddy);
+    }
+
+    *next(new_map) = *link;
+    *link = address;
+}
+
+/// Map at the physical `address`, or None at the end of the list.
+fn map_at<'a>(address: PhysAddr) -> Option<&'a mut Buddy> {
+    if address == PhysAddr::zero() {
+        return None;
+    }
+
+    // SAFETY: maps are only ever linked by insert_map, and live forever
+    Some(unsafe { &mut *phys_to_virt(address).as_mut_ptr::<Buddy>() })
+}
+
+impl Default for FrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY: the maps are only ever accessed through the allocator owning them
+unsafe impl Send for FrameAllocator {}
+
+/// Kernel page frame allocator.
+///
+/// Starts out empty, until memory is added with `add_region`. Invalid frees
+/// panic in debug builds.
+pub static FRAME_ALLOCATOR: IrqMutex<FrameAllocator> = IrqMutex::new(
+    if cfg!(debug_assertions) {
+        FrameAllocator::new_checked()
+    } else {
+        FrameAllocator::new()
+    }
+);
+
+/// Page frame allocated from `FRAME_ALLOCATOR`, and freed when dropped.
+///
+/// Dropping while holding the `FRAME_ALLOCATOR` lock will deadlock.
+#[derive(Debug, PartialEq)]
+pub struct PhysFrame {
+    frame: Frame,
+}
+
+impl PhysFrame {
+    pub fn allocate() -> Option<Self> {
+        let frame = FRAME_ALLOCATOR.lock().allocate(1)?;
+
+        Some(PhysFrame { frame })
+    }
+
+    pub fn frame(&self) -> Frame {
+        self.frame
+    }
+
+    pub fn address(&self) -> PhysAddr {
+        self.frame.start_address()
+    }
+
+    /// Give up ownership of the frame without freeing it, e.g. when handing
+    /// it over to a structure living for the rest of the kernel lifetime.
+    pub fn leak(self) -> Frame {
+        let frame = self.frame;
+        core::mem::forget(self);
+
+        frame
+    }
+}
+
+impl Drop for PhysFrame {
+    fn drop(&mut self) {
+        let _ = FRAME_ALLOCATOR.lock().free(self.frame, 1);
+    }
+}
+
+/// Consecutive page frames allocated from `FRAME_ALLOCATOR`, and freed when
+/// dropped.
+///
+/// Dropping while holding the `FRAME_ALLOCATOR` lock will deadlock.
+#[derive(Debug, PartialEq)]
+pub struct FrameRange {
+    start: Frame,
+    pages: usize,
+}
+
+impl FrameRange {
+    pub fn allocate(pages: usize) -> Option<Self> {
+        FrameRange::allocate_constrained(pages, PAGE_SIZE, PhysAddr::new(u64::MAX))
+    }
+
+    /// See `FrameAllocator::allocate_constrained`.
+    pub fn allocate_constrained(pages: usize, align: u64, max_phys_addr: PhysAddr) -> Option<Self> {
+        let start = FRAME_ALLOCATOR.lock()
+            .allocate_constrained(pages, align, max_phys_addr)?;
+
+        Some(FrameRange { start, pages })
+    }
+
+    pub fn start(&self) -> PhysAddr {
+        self.start.start_address()
+    }
+
+    pub fn end(&self) -> PhysAddr {
+        self.start() + self.pages as u64 * PAGE_SIZE
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Give up ownership of the frames without freeing them, returning the
+    /// first frame.
+    pub fn leak(self) -> Frame {
+        let start = self.start;
+        core::mem::forget(self);
+
+        start
+    }
+}
+
+impl Drop for FrameRange {
+    fn drop(&mut self) {
+        let _ = FRAME_ALLOCATOR.lock().free(self.start, self.pages);
+    }
+}
+
+/// Iterator over the maps of a `FrameAllocator`.
+pub struct MapIterator<'a> {
+    next: Option<&'a Buddy>,
+}
+
+impl<'a> Iterator for MapIterator<'a> {
+    type Item = &'a Buddy;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = self.next?;
+        self.next = map_at(map.next_map).map(|map| &*map);
+
+        Some(map)
+    }
+}
+
+impl core::fmt::Debug for FrameAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_regions(f, self.free_regions())
+    }
+}
+
+/// Write one line per free region.
+fn write_regions<I>(f: &mut core::fmt::Formatter<'_>, regions: I) -> core::fmt::Result
+where
+    I: Iterator<Item = FreeRegion>,
+{
+    for region in regions {
+        write!(
+            f,
+            "{:#018X} - {:#018X}: {} page(s) free\r\n",
+            region.start,
+            region.end(),
+            region.pages
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Number of pages covered by a map of blocks up to `order`.
+///
+/// Maps cover at least 2048 pages (8 MiB), or a single block of the highest
+/// order if that is larger.
+pub const fn page_count(order: usize) -> usize {
+    if (1 << order) > 2048 {
+        1 << order
+    } else {
+        2048
+    }
+}
+
+/// Size in bytes of the bitmap of a map of blocks up to `order`.
+///
+/// Layer `l` holds one bit per block of `2^l` pages, for layers `0..=order`.
+/// The layers are followed by one bit per page, tracking whether the page was
+/// handed out by `allocate`.
+pub const fn bitmap_size(order: usize) -> usize {
+    layers_size(order) + page_count(order) / 8
+}
+
+/// Size in bytes of the layers of the bitmap of a map of blocks up to `order`.
+const fn layers_size(order: usize) -> usize {
+    let page_count = page_count(order);
+    let bits = 2 * page_count - (page_count >> order);
+
+    bits.div_ceil(8)
+}
+
+/// Buddy map handing out blocks of up to `2^ORDER` pages.
+///
+/// `BITMAP_SIZE` must equal `bitmap_size(ORDER)`, which can not be derived
+/// from `ORDER` directly in the type.
+#[repr(C)]
+pub struct BuddyMap<const ORDER: usize, const BITMAP_SIZE: usize> {
+    // all layers back to back, starting from layer 0
+    bitmap: [u8; BITMAP_SIZE],
+    // number of free blocks on each layer, so allocations can skip layers,
+    // and whole maps, without a free block of their size
+    free_counts: [u32; MAX_LAYERS],
+    base: PhysAddr,
+    // physical address of the next map, or 0
+    next_map: PhysAddr,
+    // physical address of the next map with the same largest free block, or 0
+    next_free_map: PhysAddr,
+}
+
+/// Buddy map used by the `FrameAllocator`, handing out blocks of up to 512
+/// pages (2 MiB), i.e. Sv39 megapages.
+///
+/// Order 18 would allow 1 GiB gigapages, at the cost of 64 KiB per map and
+/// 1 GiB map windows.
+pub type Buddy = BuddyMap<MAX_ORDER, { bitmap_size(MAX_ORDER) }>;
+
+pub const MAX_ORDER: usize = 9;
+
+/// Upper bound on the number of layers of a `BuddyMap`, i.e. on `ORDER + 1`.
+const MAX_LAYERS: usize = 32;
+
+impl<const ORDER: usize, const BITMAP_SIZE: usize> BuddyMap<ORDER, BITMAP_SIZE> {
+    /// Number of pages covered by the map.
+    pub const PAGE_COUNT: usize = page_count(ORDER);
+
+    /// Evaluated by `new`, rejecting maps whose `BITMAP_SIZE` does not match
+    /// their `ORDER`, or with too many layers, at compile time.
+    const SIZE_CHECK: () = assert!(
+        BITMAP_SIZE == bitmap_size(ORDER) && ORDER < MAX_LAYERS,
+        "bitmap size does not match order",
+    );
+
+    /// Initializes a buddy-map for a memory region starting at `base`,
+    /// and places the map in memory at `address`.
+    ///
+    /// Upon initialization the whole map will be marked as occupied.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be suitably aligned for `BuddyMap`, and point to
+    /// `size_of::<Self>()` bytes of writable memory which stay valid and
+    /// otherwise unused for as long as the map lives.
+    pub unsafe fn new(base: Frame, address: VirtAddr) -> &'static mut Self {
+        let () = Self::SIZE_CHECK;
+
+        let ptr = address.as_mut_ptr::<Self>();
+
+        // initialize all layers as used, with no pages handed out
+        addr_of_mut!((*ptr).bitmap).write_bytes(0xFFu8, 1);
+        (addr_of_mut!((*ptr).bitmap) as *mut u8)
+            .add(layers_size(ORDER))
+            .write_bytes(0u8, Self::PAGE_COUNT / 8);
+        addr_of_mut!((*ptr).free_counts).write([0; MAX_LAYERS]);
+
+        addr_of_mut!((*ptr).base).write(base.start_address());
+        addr_of_mut!((*ptr).next_map).write(PhysAddr::zero());
+        addr_of_mut!((*ptr).next_free_map).write(PhysAddr::zero());
+
+        // SAFETY: all fields of BuddyMap have been initialized
+        &mut *ptr
+    }
+
+    /// Attempt to allocate `page_count` consecutive pages.
+    ///
+    /// The pages are taken from the smallest free block able to hold them, so
+    /// the returned frame is aligned to `page_count` rounded up to the next
+    /// power of two. Any pages left over in the block are freed immediately.
+    pub fn allocate(&mut self, page_count: usize) -> Option<Frame> {
+        self.allocate_constrained(page_count, PAGE_SIZE, PhysAddr::new(u64::MAX))
+    }
+
+    /// Like `allocate`, but the returned frame is also aligned to `align`
+    /// bytes, and the last page ends at or below `max_phys_addr`.
+    ///
+    /// `align` must be a power of two.
+    pub fn allocate_constrained(
+        &mut self,
+        page_count: usize,
+        align: u64,
+        max_phys_addr: PhysAddr,
+    ) -> Option<Frame> {
+        if page_count == 0 || !align.is_power_of_two() || self.base > max_phys_addr {
+            return None;
+        }
+
+        let order = page_count.next_power_of_two().trailing_zeros() as usize;
+        if order > ORDER {
+            return None;
+        }
+
+        let size = page_count as u64 * PAGE_SIZE;
+        let limit = max_phys_addr.as_u64();
+        let fits = |address: PhysAddr| {
+            address.is_aligned(align) &&
+                address.as_u64().checked_add(size - 1).is_some_and(|end| end <= limit)
+        };
+
+        // a block smaller than `align` can only be aligned at its start, so
+        // splitting always keeps the first half. Layers without free blocks
+        // are never scanned, so full maps fail without touching the bitmap.
+        let (offset, layer) = (order..=ORDER)
+            .filter(|layer| self.free_counts[*layer] > 0)
+            .find_map(|layer| Some((self.find_free(layer, fits)?, layer)))?;
+
+        for layer in ((order + 1)..=layer).rev() {
+            self.try_split(offset, layer);
+        }
+        self.mark_one(offset, order);
+
+        let block_size = 1 << order;
+        for page_offset in (offset + page_count)..(offset + block_size) {
+            self.free_page(page_offset);
+        }
+
+        for page_offset in offset..(offset + page_count) {
+            self.set_allocated(page_offset, true);
+        }
+
+        Some(Frame::containing_address(self.offset_to_address(offset)))
+    }
+
+    /// Free page(s) previously handed out by `allocate`.
+    ///
+    /// Nothing is freed unless all of the pages are currently allocated.
+    pub fn free(&mut self, start: Frame, page_count: usize) -> Result<(), FreeError> {
+        self.check_free(start, page_count)?;
+
+        let offset = self.address_to_offset(start.start_address());
+        for page_offset in offset..(offset + page_count) {
+            self.set_allocated(page_offset, false);
+            self.free_page(page_offset);
+        }
+
+        Ok(())
+    }
+
+    /// Check that page(s) can be freed, i.e. that all of them are covered by
+    /// the map and were handed out by `allocate`.
+    pub fn check_free(&self, start: Frame, page_count: usize) -> Result<(), FreeError> {
+        for page in 0..page_count {
+            let address = start.start_address() + (page << 12) as u64;
+
+            if !self.in_range(address) {
+                return Err(FreeError::OutOfRange(address));
+            }
+
+            let offset = self.address_to_offset(address);
+            if self.check(offset) {
+                return Err(FreeError::DoubleFree(address));
+            }
+
+            if !self.is_allocated(offset) {
+                return Err(FreeError::NotAllocated(address));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark specific page(s) as free, regardless of their current state.
     ///
-    /// Both `base` and `address` must be page aligned memory addresses.
-    pub fn free(&mut self, page_base: u64, page_count: usize) -> bool {
-        if !self.in_range(page_base) {
+    /// This hands memory over to the map, e.g. after initialization. Pages
+    /// outside the map are ignored.
+    pub fn add_free(&mut self, start: Frame, page_count: usize) -> bool {
+        if !self.in_range(start.start_address()) {
             return false;
         }
 
-        let offset = self.address_to_offset(page_base);
+        let offset = self.address_to_offset(start.start_address());
         for page in 0..page_count {
             let page_offset = offset + page;
 
@@ -86,15 +825,9 @@ impl Buddy {
                 break;
             }
 
-            if self.check(page_offset) {
-                continue;
-            }
-
-            self.free_one(page_offset, 0);
-            for layer in 0..=2 {
-                if !self.try_join(page_offset, layer) {
-                    break;
-                }
+            self.set_allocated(page_offset, false);
+            if !self.check(page_offset) {
+                self.free_page(page_offset);
             }
         }
 
@@ -102,14 +835,12 @@ impl Buddy {
     }
 
     /// Mark specific page(s) as allocated.
-    ///
-    /// Both `base` and `address` must be page aligned memory addresses.
-    pub fn mark(&mut self, page_base: u64, page_count: usize) -> bool {
-        if !self.in_range(page_base) {
+    pub fn mark(&mut self, start: Frame, page_count: usize) -> bool {
+        if !self.in_range(start.start_address()) {
             return false;
         }
 
-        let offset = self.address_to_offset(page_base);
+        let offset = self.address_to_offset(start.start_address());
         for page in 0..page_count {
             let page_offset = offset + page;
 
@@ -117,7 +848,7 @@ impl Buddy {
                 break;
             }
 
-            for layer in (1..=3).rev() {
+            for layer in (1..=ORDER).rev() {
                 if self.check_layer(page_offset, layer) {
                     self.try_split(page_offset, layer);
                 }
@@ -129,22 +860,50 @@ impl Buddy {
         true
     }
 
+    /// Iterate over free memory, merging adjacent blocks.
+    pub fn free_regions(&self) -> impl Iterator<Item = FreeRegion> + '_ {
+        MergedRegions::new(self.free_blocks())
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats::from_regions(Self::PAGE_COUNT, self.free_regions())
+    }
+
+    /// Order of the largest free block, or `None` if the map is full.
+    fn largest_free(&self) -> Option<usize> {
+        (0..=ORDER).rev().find(|layer| self.free_counts[*layer] > 0)
+    }
+
+    fn free_blocks(&self) -> FreeBlocks<'_, ORDER, BITMAP_SIZE> {
+        FreeBlocks { map: self, offset: 0 }
+    }
+
     /// Convert page address to page offset within the map.
-    fn address_to_offset(&self, address: u64) -> usize {
+    fn address_to_offset(&self, address: PhysAddr) -> usize {
         ((address - self.base) >> 12) as usize
     }
 
-    fn offset_to_address(&self, offset: usize) -> u64 {
+    fn offset_to_address(&self, offset: usize) -> PhysAddr {
         self.base + (offset << 12) as u64
     }
 
-    fn in_range(&self, address: u64) -> bool {
-        address >= self.base && address < self.base + (PAGE_COUNT << 12) as u64
+    fn in_range(&self, address: PhysAddr) -> bool {
+        address >= self.base && address < self.base + (Self::PAGE_COUNT << 12) as u64
+    }
+
+    /// Bit index of the block containing page `offset` on `layer`.
+    fn bit_index(offset: usize, layer: usize) -> usize {
+        assert!(layer <= ORDER, "layer out of range");
+
+        // layer l starts after the 2^-1 + ... + 2^-l fractions of layer 0
+        let layer_start = 2 * Self::PAGE_COUNT - ((2 * Self::PAGE_COUNT) >> layer);
+
+        layer_start + (offset >> layer)
     }
 
     /// Returns true if free, else false.
     fn check(&self, offset: usize) -> bool {
-        for layer in (0..=3).rev() {
+        for layer in (0..=ORDER).rev() {
             if self.check_layer(offset, layer) {
                 return true;
             }
@@ -155,133 +914,189 @@ impl Buddy {
 
     /// Returns true if free, else false.
     fn check_layer(&self, offset: usize, layer: usize) -> bool {
-        let layer_offset = offset >> layer;
-        let byte_offset = layer_offset / 8;
-        let bit_offset = 7 - (layer_offset % 8);
-
-        let byte = match layer {
-            0 => self.l0[byte_offset],
-            1 => self.l1[byte_offset],
-            2 => self.l2[byte_offset],
-            3 => self.l3[byte_offset],
-            _ => panic!("layer out of range"),
-        };
+        let bit_index = Self::bit_index(offset, layer);
+        let byte = self.bitmap[bit_index / 8];
+        let bit_offset = 7 - (bit_index % 8);
 
         (byte >> bit_offset) & USED == FREE
     }
 
+    /// Free a single used page, joining it with its buddies where possible.
+    fn free_page(&mut self, offset: usize) {
+        self.free_one(offset, 0);
+        for layer in 0..ORDER {
+            if !self.try_join(offset, layer) {
+                break;
+            }
+        }
+    }
+
+    /// Returns true if the page was handed out by `allocate`.
+    fn is_allocated(&self, offset: usize) -> bool {
+        let byte = self.bitmap[layers_size(ORDER) + offset / 8];
+
+        (byte >> (7 - offset % 8)) & 1 != 0
+    }
+
+    fn set_allocated(&mut self, offset: usize, allocated: bool) {
+        let byte = &mut self.bitmap[layers_size(ORDER) + offset / 8];
+        let mask = 1 << (7 - offset % 8);
+
+        if allocated {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+
+    /// Find the first free block on `layer` with an address accepted by
+    /// `accept`, returning its page offset.
+    fn find_free<F: Fn(PhysAddr) -> bool>(&self, layer: usize, accept: F) -> Option<usize> {
+        let block_count = Self::PAGE_COUNT >> layer;
+
+        let mut block = 0;
+        while block < block_count {
+            let bit_index = Self::bit_index(block << layer, layer);
+
+            // skip over fully used bytes lying within the layer
+            if bit_index % 8 == 0 && block + 8 <= block_count &&
+                self.bitmap[bit_index / 8] == 0xFF
+            {
+                block += 8;
+                continue;
+            }
+
+            if self.check_layer(block << layer, layer) &&
+                accept(self.offset_to_address(block << layer))
+            {
+                return Some(block << layer);
+            }
+
+            block += 1;
+        }
+
+        None
+    }
+
     fn try_join(&mut self, offset: usize, layer: usize) -> bool {
-        if layer > 2 {
+        if layer >= ORDER {
             return false;
         }
 
-        let layer_offset = offset >> layer;
-        let byte_offset = layer_offset / 8;
+        // layers start at even bit indices, so both buddies share a byte
+        let bit_index = Self::bit_index(offset, layer);
+        let byte_offset = bit_index / 8;
         // always pick the even bit to simplify the join check
-        let bit_offset = (7 - (layer_offset % 8)) & !1;
+        let bit_offset = (7 - (bit_index % 8)) & !1;
 
-        let upper_layer_offset = layer_offset >> 1;
-        let upper_byte_offset = upper_layer_offset / 8;
-        let upper_bit_offset = 7 - (upper_layer_offset % 8);
-
-        let (byte, upper) = match layer {
-            0 => (&mut self.l0[byte_offset], &mut self.l1[upper_byte_offset]),
-            1 => (&mut self.l1[byte_offset], &mut self.l2[upper_byte_offset]),
-            2 => (&mut self.l2[byte_offset], &mut self.l3[upper_byte_offset]),
-            _ => panic!("layer out of range"),
-        };
+        let upper_bit_index = Self::bit_index(offset, layer + 1);
+        let upper_byte_offset = upper_bit_index / 8;
+        let upper_bit_offset = 7 - (upper_bit_index % 8);
 
-        if (*byte >> bit_offset) & 0b11 != FREE {
+        if (self.bitmap[byte_offset] >> bit_offset) & 0b11 != FREE {
             return false;
         }
 
         // mark two layer l bits as used, freeing up one bit on layer l + 1
-        *byte |= 0b11 << bit_offset;
-        *upper &= !(USED << upper_bit_offset);
+        self.bitmap[byte_offset] |= 0b11 << bit_offset;
+        self.bitmap[upper_byte_offset] &= !(USED << upper_bit_offset);
+        self.free_counts[layer] -= 2;
+        self.free_counts[layer + 1] += 1;
 
         true
     }
 
     fn try_split(&mut self, offset: usize, layer: usize) -> bool {
-        if layer == 0 || layer > 3 {
+        if layer == 0 || layer > ORDER {
             return false;
         }
 
-        let layer_offset = offset >> layer;
-        let byte_offset = layer_offset / 8;
-        let bit_offset = 7 - (layer_offset % 8);
+        let bit_index = Self::bit_index(offset, layer);
+        let byte_offset = bit_index / 8;
+        let bit_offset = 7 - (bit_index % 8);
 
-        let lower_layer_offset = layer_offset << 1;
-        let lower_byte_offset = lower_layer_offset / 8;
-        let lower_bit_offset = 7 - (lower_layer_offset % 8);
+        // the first of the two halves, at an even bit index
+        let lower_bit_index = Self::bit_index((offset >> layer) << layer, layer - 1);
+        let lower_byte_offset = lower_bit_index / 8;
+        let lower_bit_offset = 7 - (lower_bit_index % 8);
 
-        let (byte, lower) = match layer {
-            1 => (&mut self.l1[byte_offset], &mut self.l0[lower_byte_offset]),
-            2 => (&mut self.l2[byte_offset], &mut self.l1[lower_byte_offset]),
-            3 => (&mut self.l3[byte_offset], &mut self.l2[lower_byte_offset]),
-            _ => panic!("layer out of range"),
-        };
-
-        if (*byte >> bit_offset) & 1 != FREE {
+        if (self.bitmap[byte_offset] >> bit_offset) & 1 != FREE {
             return false;
         }
 
         // mark layer l bit as used, freeing up two bits on layer l - 1
-        *byte |= USED << bit_offset;
+        self.bitmap[byte_offset] |= USED << bit_offset;
         // layer l - 1 offset is always even given layer l offset is aligned
         // to layer size, so we can directly zero both ajacent bits
-        *lower &= !(0b11 << (lower_bit_offset - 1));
+        self.bitmap[lower_byte_offset] &= !(0b11 << (lower_bit_offset - 1));
+        self.free_counts[layer] -= 1;
+        self.free_counts[layer - 1] += 2;
 
         true
     }
 
     fn free_one(&mut self, offset: usize, layer: usize) {
-        let layer_offset = offset >> layer;
-        let byte_offset = layer_offset / 8;
-        let bit_offset = 7 - (layer_offset % 8);
-        let mask = !(USED << bit_offset);
-
-        match layer {
-            0 => self.l0[byte_offset] &= mask,
-            1 => self.l1[byte_offset] &= mask,
-            2 => self.l2[byte_offset] &= mask,
-            3 => self.l3[byte_offset] &= mask,
-            _ => panic!("layer out of range"),
+        if self.check_layer(offset, layer) {
+            return;
         }
+
+        let bit_index = Self::bit_index(offset, layer);
+        let bit_offset = 7 - (bit_index % 8);
+
+        self.bitmap[bit_index / 8] &= !(USED << bit_offset);
+        self.free_counts[layer] += 1;
     }
 
     fn mark_one(&mut self, offset: usize, layer: usize) {
-        let layer_offset = offset >> layer;
-        let byte_offset = layer_offset / 8;
-        let bit_offset = 7 - (layer_offset % 8);
-        let mask = USED << bit_offset;
-
-        match layer {
-            0 => self.l0[byte_offset] |= mask,
-            1 => self.l1[byte_offset] |= mask,
-            2 => self.l2[byte_offset] |= mask,
-            3 => self.l3[byte_offset] |= mask,
-            _ => panic!("layer out of range"),
+        if !self.check_layer(offset, layer) {
+            return;
         }
+
+        let bit_index = Self::bit_index(offset, layer);
+        let bit_offset = 7 - (bit_index % 8);
+
+        self.bitmap[bit_index / 8] |= USED << bit_offset;
+        self.free_counts[layer] -= 1;
     }
 }
 
-impl core::fmt::Debug for Buddy {
+impl<const ORDER: usize, const BITMAP_SIZE: usize> core::fmt::Debug for BuddyMap<ORDER, BITMAP_SIZE> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        // FIXME: Line breaks!
-        for layer in (0..=3).rev() {
-            for offset in (0..PAGE_COUNT).step_by(1 << layer) {
-                if self.check_layer(offset, layer) {
-                    write!(
-                        f,
-                        "{:#018X}: {} page(s) free\r\n",
-                        self.offset_to_address(offset),
-                        1 << layer
-                    )?;
-                }
+        write_regions(f, self.free_regions())
+    }
+}
+
+/// Iterator over the free blocks of a map, in order of address.
+struct FreeBlocks<'a, const ORDER: usize, const BITMAP_SIZE: usize> {
+    map: &'a BuddyMap<ORDER, BITMAP_SIZE>,
+    offset: usize,
+}
+
+impl<'a, const ORDER: usize, const BITMAP_SIZE: usize> Iterator for FreeBlocks<'a, ORDER, BITMAP_SIZE> {
+    type Item = FreeRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset < BuddyMap::<ORDER, BITMAP_SIZE>::PAGE_COUNT {
+            let offset = self.offset;
+
+            // free blocks are aligned to their size, and never overlap
+            let layer = (0..=ORDER)
+                .rev()
+                .find(|layer| offset.is_multiple_of(1 << layer) && self.map.check_layer(offset, *layer));
+
+            match layer {
+                Some(layer) => {
+                    self.offset += 1 << layer;
+
+                    return Some(FreeRegion {
+                        start: self.map.offset_to_address(offset),
+                        pages: 1 << layer,
+                    });
+                },
+                None => self.offset += 1,
             }
         }
 
-        Ok(())
+        None
     }
 }
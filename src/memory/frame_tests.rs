@@ -0,0 +1,740 @@
+use super::*;
+
+use crate::memory::test_util::{frame, new_region, phys};
+
+/// Map with the original four layers, handing out up to 8 pages.
+type SmallBuddy = BuddyMap<3, { bitmap_size(3) }>;
+
+/// Map handing out Sv39 gigapages.
+type HugeBuddy = BuddyMap<18, { bitmap_size(18) }>;
+
+/// Allocate a map on the heap, covering memory starting at `base`.
+fn new_map<const ORDER: usize, const BITMAP_SIZE: usize>(
+    base: u64,
+) -> &'static mut BuddyMap<ORDER, BITMAP_SIZE> {
+    let storage = Box::leak(Box::new(
+        core::mem::MaybeUninit::<BuddyMap<ORDER, BITMAP_SIZE>>::uninit()
+    ));
+
+    unsafe { BuddyMap::new(frame(base), VirtAddr::new(storage.as_mut_ptr() as u64)) }
+}
+
+fn new_buddy(base: u64) -> &'static mut SmallBuddy {
+    new_map(base)
+}
+
+#[test]
+fn new_map_is_fully_used() {
+    let buddy = new_buddy(0x8000_0000);
+
+    assert_eq!(buddy.allocate(1), None);
+}
+
+#[test]
+fn allocate_freed_page() {
+    let buddy = new_buddy(0x8000_0000);
+
+    assert!(buddy.add_free(frame(0x8000_3000), 1));
+    assert_eq!(buddy.allocate(1), Some(frame(0x8000_3000)));
+    assert_eq!(buddy.allocate(1), None);
+}
+
+#[test]
+fn new_map_layout() {
+    let buddy = new_buddy(0x8000_0000);
+
+    assert_eq!(buddy.base, phys(0x8000_0000));
+    assert_eq!(buddy.next_map, PhysAddr::zero());
+    assert!((0..SmallBuddy::PAGE_COUNT).all(|offset| !buddy.check(offset)));
+}
+
+#[test]
+fn out_of_range() {
+    let buddy = new_buddy(0x8000_0000);
+
+    assert!(!buddy.add_free(frame(0x7FFF_F000), 1));
+    assert!(!buddy.mark(frame(0x8080_0000), 1));
+
+    // ranges running past the end of the map are clipped
+    assert!(buddy.add_free(frame(0x807F_F000), 2));
+    assert_eq!(buddy.allocate(1), Some(frame(0x807F_F000)));
+    assert_eq!(buddy.allocate(1), None);
+}
+
+#[test]
+fn free_coalesces_buddies() {
+    let buddy = new_buddy(0x8000_0000);
+
+    for page in &[5, 0, 7, 2, 1, 6, 3] {
+        buddy.add_free(frame(0x8000_0000 + page * 0x1000), 1);
+    }
+
+    // 0..4 joined all the way up to layer 2, 4 is still used
+    assert!(buddy.check_layer(0, 2));
+    assert!(buddy.check_layer(6, 1));
+    assert!(buddy.check_layer(5, 0));
+    assert!(!buddy.check_layer(0, 3));
+    assert!(!buddy.check(4));
+
+    buddy.add_free(frame(0x8000_4000), 1);
+    assert!(buddy.check_layer(0, 3));
+    assert!((0..8).all(|offset| !buddy.check_layer(offset, 0)));
+    assert!((0..8).all(|offset| !buddy.check_layer(offset, 1)));
+    assert!((0..8).all(|offset| !buddy.check_layer(offset, 2)));
+}
+
+#[test]
+fn add_free_is_idempotent() {
+    let buddy = new_buddy(0x8000_0000);
+
+    buddy.add_free(frame(0x8000_0000), 8);
+    buddy.add_free(frame(0x8000_2000), 2);
+
+    assert!(buddy.check_layer(0, 3));
+    assert!(!buddy.check_layer(2, 1));
+}
+
+#[test]
+fn mark_splits_blocks() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), 8);
+
+    buddy.mark(frame(0x8000_5000), 1);
+
+    assert!(!buddy.check_layer(0, 3));
+    assert!(buddy.check_layer(0, 2));
+    assert!(buddy.check_layer(6, 1));
+    assert!(buddy.check_layer(4, 0));
+    assert!(!buddy.check(5));
+
+    buddy.mark(frame(0x8000_0000), 8);
+    assert!((0..8).all(|offset| !buddy.check(offset)));
+}
+
+#[test]
+fn join_and_split() {
+    let buddy = new_buddy(0x8000_0000);
+
+    // only one half of the pair is free
+    buddy.free_one(2, 0);
+    assert!(!buddy.try_join(2, 0));
+
+    buddy.free_one(3, 0);
+    assert!(buddy.try_join(3, 0));
+    assert!(buddy.check_layer(2, 1));
+    assert!(!buddy.check_layer(2, 0) && !buddy.check_layer(3, 0));
+
+    // nothing to split on a used block, nor on layer 0 or past the top layer
+    assert!(!buddy.try_split(0, 1));
+    assert!(!buddy.try_split(2, 0));
+    assert!(!buddy.try_join(0, 3));
+
+    assert!(buddy.try_split(2, 1));
+    assert!(buddy.check_layer(2, 0) && buddy.check_layer(3, 0));
+    assert!(!buddy.check_layer(2, 1));
+}
+
+#[test]
+fn allocate_until_exhausted() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), SmallBuddy::PAGE_COUNT);
+
+    for block in 0..(SmallBuddy::PAGE_COUNT / 8) {
+        let address = 0x8000_0000 + (block * 8 * 0x1000) as u64;
+        assert_eq!(buddy.allocate(8), Some(frame(address)));
+    }
+
+    assert_eq!(buddy.allocate(1), None);
+
+    assert_eq!(buddy.free(frame(0x8000_8000), 1), Ok(()));
+    assert_eq!(buddy.allocate(2), None);
+    assert_eq!(buddy.allocate(1), Some(frame(0x8000_8000)));
+}
+
+#[test]
+fn free_rejects_double_free() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), 8);
+
+    assert_eq!(buddy.allocate(2), Some(frame(0x8000_0000)));
+    assert_eq!(buddy.free(frame(0x8000_0000), 2), Ok(()));
+    assert_eq!(buddy.free(frame(0x8000_0000), 2), Err(FreeError::DoubleFree(phys(0x8000_0000))));
+
+    // pages joined into a larger block are free as well
+    assert_eq!(buddy.free(frame(0x8000_4000), 1), Err(FreeError::DoubleFree(phys(0x8000_4000))));
+}
+
+#[test]
+fn free_rejects_pages_never_allocated() {
+    let buddy = new_buddy(0x8000_0000);
+
+    assert_eq!(buddy.free(frame(0x8000_0000), 1), Err(FreeError::NotAllocated(phys(0x8000_0000))));
+
+    buddy.add_free(frame(0x8000_0000), 8);
+    assert_eq!(buddy.allocate(4), Some(frame(0x8000_0000)));
+    buddy.mark(frame(0x8000_4000), 1);
+
+    assert_eq!(buddy.free(frame(0x8000_0000), 5), Err(FreeError::NotAllocated(phys(0x8000_4000))));
+
+    // a partially invalid free leaves all pages allocated
+    assert!((0..5).all(|offset| !buddy.check(offset)));
+    assert_eq!(buddy.free(frame(0x8000_0000), 4), Ok(()));
+}
+
+#[test]
+fn free_rejects_partially_out_of_range() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x807F_F000), 1);
+
+    assert_eq!(buddy.allocate(1), Some(frame(0x807F_F000)));
+    assert_eq!(buddy.free(frame(0x807F_F000), 2), Err(FreeError::OutOfRange(phys(0x8080_0000))));
+    assert_eq!(buddy.free(frame(0x7FFF_F000), 1), Err(FreeError::OutOfRange(phys(0x7FFF_F000))));
+    assert_eq!(buddy.allocate(1), None);
+}
+
+#[test]
+fn allocate_splits_blocks() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), 8);
+
+    assert_eq!(buddy.allocate(2), Some(frame(0x8000_0000)));
+    assert_eq!(buddy.allocate(1), Some(frame(0x8000_2000)));
+    assert_eq!(buddy.allocate(4), Some(frame(0x8000_4000)));
+    assert_eq!(buddy.allocate(1), Some(frame(0x8000_3000)));
+    assert_eq!(buddy.allocate(1), None);
+}
+
+#[test]
+fn allocate_frees_unused_tail() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), 8);
+
+    assert_eq!(buddy.allocate(3), Some(frame(0x8000_0000)));
+    assert_eq!(buddy.allocate(4), Some(frame(0x8000_4000)));
+    assert_eq!(buddy.allocate(1), Some(frame(0x8000_3000)));
+    assert_eq!(buddy.allocate(1), None);
+
+    assert_eq!(buddy.free(frame(0x8000_0000), 3), Ok(()));
+    assert_eq!(buddy.allocate(2), Some(frame(0x8000_0000)));
+}
+
+#[test]
+fn allocate_naturally_aligned() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_1000), 8);
+
+    assert_eq!(buddy.allocate(8), None);
+    assert_eq!(buddy.allocate(4), Some(frame(0x8000_4000)));
+    assert_eq!(buddy.allocate(4), None);
+    assert_eq!(buddy.allocate(2), Some(frame(0x8000_2000)));
+}
+
+#[test]
+fn allocate_invalid_sizes() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), SmallBuddy::PAGE_COUNT);
+
+    assert_eq!(buddy.allocate(0), None);
+    assert_eq!(buddy.allocate(9), None);
+    assert_eq!(buddy.allocate(8), Some(frame(0x8000_0000)));
+}
+
+#[test]
+fn allocate_aligned() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_1000), 15);
+
+    assert_eq!(buddy.allocate_constrained(1, 0x4000, phys(u64::MAX)), Some(frame(0x8000_4000)));
+    assert_eq!(buddy.allocate_constrained(2, 0x8000, phys(u64::MAX)), Some(frame(0x8000_8000)));
+    assert_eq!(buddy.allocate_constrained(1, 0x8000, phys(u64::MAX)), None);
+    assert_eq!(buddy.allocate_constrained(1, 0x3000, phys(u64::MAX)), None);
+
+    // alignment below a page is no constraint at all
+    assert_eq!(buddy.allocate_constrained(1, 1, phys(u64::MAX)), Some(frame(0x8000_1000)));
+}
+
+#[test]
+fn allocate_below_limit() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), 8);
+    buddy.mark(frame(0x8000_0000), 2);
+
+    assert_eq!(buddy.allocate_constrained(1, 0x1000, phys(0x8000_1FFF)), None);
+    assert_eq!(buddy.allocate_constrained(2, 0x1000, phys(0x8000_2FFF)), None);
+    assert_eq!(buddy.allocate_constrained(2, 0x1000, phys(0x8000_3FFF)), Some(frame(0x8000_2000)));
+    assert_eq!(buddy.allocate_constrained(1, 0x1000, phys(0x7FFF_FFFF)), None);
+    assert_eq!(buddy.allocate_constrained(1, 0x1000, phys(u64::MAX)), Some(frame(0x8000_4000)));
+}
+
+#[test]
+fn free_regions_merge_blocks() {
+    let buddy = new_buddy(0x8000_0000);
+    buddy.add_free(frame(0x8000_1000), 12);
+
+    let regions: Vec<_> = buddy.free_regions().collect();
+    assert_eq!(regions, [FreeRegion { start: phys(0x8000_1000), pages: 12 }]);
+
+    buddy.mark(frame(0x8000_5000), 1);
+    buddy.add_free(frame(0x807F_F000), 1);
+
+    let regions: Vec<_> = buddy.free_regions().collect();
+    assert_eq!(regions, [
+        FreeRegion { start: phys(0x8000_1000), pages: 4 },
+        FreeRegion { start: phys(0x8000_6000), pages: 7 },
+        FreeRegion { start: phys(0x807F_F000), pages: 1 },
+    ]);
+}
+
+#[test]
+fn stats_and_summary() {
+    let buddy = new_buddy(0x8000_0000);
+    assert_eq!(buddy.stats(), FrameStats {
+        total: 2048,
+        free: 0,
+        used: 2048,
+        largest_free: 0,
+    });
+
+    buddy.add_free(frame(0x8000_0000), 1024);
+    buddy.add_free(frame(0x8060_0000), 3);
+    buddy.mark(frame(0x8000_0000), 1);
+
+    let stats = buddy.stats();
+    assert_eq!(stats, FrameStats {
+        total: 2048,
+        free: 1026,
+        used: 1022,
+        largest_free: 1023,
+    });
+    assert_eq!(
+        format!("{}", stats),
+        "4 MiB free of 8 MiB (3 MiB used), largest free region 3 MiB"
+    );
+    assert_eq!(
+        format!("{:?}", buddy),
+        "0x0000000080001000 - 0x0000000080400000: 1023 page(s) free\r\n\
+         0x0000000080600000 - 0x0000000080603000: 3 page(s) free\r\n"
+    );
+}
+
+#[test]
+fn bitmap_sizes() {
+    // four layers, followed by the allocated pages
+    assert_eq!(bitmap_size(3), 256 + 128 + 64 + 32 + 256);
+    assert_eq!(core::mem::size_of::<SmallBuddy>(), 736 + 4 * MAX_LAYERS + 24);
+    assert_eq!(Buddy::PAGE_COUNT, 2048);
+    assert_eq!(HugeBuddy::PAGE_COUNT, 1 << 18);
+}
+
+#[test]
+fn allocate_megapage() {
+    let buddy = new_map::<{ MAX_ORDER }, { bitmap_size(MAX_ORDER) }>(0x8000_0000);
+    buddy.add_free(frame(0x8000_1000), Buddy::PAGE_COUNT - 1);
+
+    assert_eq!(buddy.allocate(512), Some(frame(0x8020_0000)));
+    assert_eq!(buddy.allocate(512), Some(frame(0x8040_0000)));
+    assert_eq!(buddy.allocate(512), Some(frame(0x8060_0000)));
+    assert_eq!(buddy.allocate(512), None);
+    assert_eq!(buddy.allocate(256), Some(frame(0x8010_0000)));
+
+    assert_eq!(buddy.free(frame(0x8040_0000), 512), Ok(()));
+    assert_eq!(buddy.allocate(513), None);
+    assert_eq!(buddy.allocate(512), Some(frame(0x8040_0000)));
+}
+
+#[test]
+fn allocate_gigapage() {
+    let buddy = new_map::<18, { bitmap_size(18) }>(0x8000_0000);
+    buddy.add_free(frame(0x8000_0000), HugeBuddy::PAGE_COUNT);
+
+    assert_eq!(buddy.allocate(1 << 18), Some(frame(0x8000_0000)));
+    assert_eq!(buddy.allocate(1), None);
+
+    assert_eq!(buddy.free(frame(0x8000_0000), 1 << 18), Ok(()));
+    assert_eq!(buddy.allocate(1), Some(frame(0x8000_0000)));
+    assert_eq!(buddy.allocate(1 << 17), Some(frame(0xA000_0000)));
+    assert_eq!(buddy.allocate(1 << 17), None);
+}
+
+/// Collect the addresses of all free pages, in order.
+fn free_pages(frames: &FrameAllocator) -> Vec<u64> {
+    frames.maps()
+        .flat_map(|map| {
+            (0..Buddy::PAGE_COUNT)
+                .filter(move |offset| map.check(*offset))
+                .map(move |offset| map.offset_to_address(offset).as_u64())
+        })
+        .collect()
+}
+
+#[test]
+fn allocator_chains_maps() {
+    let base = new_region(3);
+    let mut frames = FrameAllocator::new();
+
+    // second window first, to exercise ordered insertion
+    unsafe {
+        frames.add_region(frame(base + MAP_SIZE), Buddy::PAGE_COUNT);
+        frames.add_region(frame(base + MAP_SIZE - 2 * PAGE_SIZE), 2);
+        frames.add_region(frame(base + 2 * MAP_SIZE + PAGE_SIZE), 1);
+    }
+
+    let bases: Vec<_> = frames.maps().map(|map| map.base.as_u64()).collect();
+    assert_eq!(bases, [base, base + MAP_SIZE, base + 2 * MAP_SIZE]);
+
+    // all three maps live in the first page of the first region
+    let free = free_pages(&frames);
+    assert_eq!(free.len(), 2 + Buddy::PAGE_COUNT - 1 + 1);
+    assert_eq!(free[0], base + MAP_SIZE - 2 * PAGE_SIZE);
+    assert_eq!(free[2], base + MAP_SIZE + PAGE_SIZE);
+    assert_eq!(free.last(), Some(&(base + 2 * MAP_SIZE + PAGE_SIZE)));
+}
+
+#[test]
+fn allocator_routes_across_maps() {
+    let base = new_region(2);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 2 * Buddy::PAGE_COUNT) };
+
+    // the first page holds both maps, leave a page at either side of the
+    // boundary between them
+    assert_eq!(frames.maps().count(), 2);
+    assert!(frames.mark(frame(base + PAGE_SIZE), Buddy::PAGE_COUNT - 2));
+    assert!(frames.mark(frame(base + MAP_SIZE + PAGE_SIZE), Buddy::PAGE_COUNT - 1));
+    assert!(!frames.mark(frame(base + 2 * MAP_SIZE), 1));
+
+    assert_eq!(frames.allocate(1), Some(frame(base + MAP_SIZE - PAGE_SIZE)));
+    assert_eq!(frames.allocate(1), Some(frame(base + MAP_SIZE)));
+    assert_eq!(frames.allocate(1), None);
+
+    assert_eq!(frames.free(frame(base + MAP_SIZE - PAGE_SIZE), 2), Ok(()));
+    assert_eq!(frames.allocate(2), None);
+    assert_eq!(frames.allocate(1), Some(frame(base + MAP_SIZE - PAGE_SIZE)));
+}
+
+#[test]
+fn allocator_free_regions_span_maps() {
+    let base = new_region(2);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 2 * Buddy::PAGE_COUNT) };
+    frames.mark(frame(base + 0x1000), 1);
+
+    let regions: Vec<_> = frames.free_regions().collect();
+    assert_eq!(regions, [FreeRegion {
+        start: phys(base + 0x2000),
+        pages: 2 * Buddy::PAGE_COUNT - 2,
+    }]);
+
+    assert_eq!(frames.stats(), FrameStats {
+        total: 2 * Buddy::PAGE_COUNT,
+        free: 2 * Buddy::PAGE_COUNT - 2,
+        used: 2,
+        largest_free: 2 * Buddy::PAGE_COUNT - 2,
+    });
+}
+
+#[test]
+fn allocator_rejects_invalid_frees() {
+    let base = new_region(2);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 2 * Buddy::PAGE_COUNT) };
+    assert!(frames.mark(frame(base + PAGE_SIZE), 2 * Buddy::PAGE_COUNT - 2));
+
+    let page = frames.allocate(1).unwrap().start_address().as_u64();
+    assert_eq!(page, base + 2 * MAP_SIZE - PAGE_SIZE);
+
+    assert_eq!(frames.free(frame(page), 2), Err(FreeError::OutOfRange(phys(base + 2 * MAP_SIZE))));
+    assert_eq!(frames.free(frame(page - PAGE_SIZE), 2), Err(FreeError::NotAllocated(phys(page - PAGE_SIZE))));
+
+    // nothing was freed by the failed attempts
+    assert_eq!(frames.allocate(1), None);
+
+    assert_eq!(frames.free(frame(page), 1), Ok(()));
+    assert_eq!(frames.free(frame(page), 1), Err(FreeError::DoubleFree(phys(page))));
+}
+
+#[test]
+fn allocator_constrained() {
+    let base = new_region(3);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 3 * Buddy::PAGE_COUNT) };
+
+    // only the first map lies below the limit
+    let limit = base + MAP_SIZE - 1;
+    for _ in 0..(Buddy::PAGE_COUNT / 512 - 1) {
+        let block = frames.allocate_constrained(512, PAGE_SIZE, phys(limit)).unwrap().start_address().as_u64();
+        assert!(block + 512 * PAGE_SIZE - 1 <= limit);
+    }
+
+    // the map storage page breaks up the first block
+    assert_eq!(frames.allocate_constrained(512, PAGE_SIZE, phys(limit)), None);
+    assert_eq!(frames.allocate_constrained(256, PAGE_SIZE, phys(limit)), Some(frame(base + 256 * PAGE_SIZE)));
+
+    // the first page of the first map holds the maps
+    let expected = if base.is_multiple_of(2 * MAP_SIZE) {
+        base + 2 * MAP_SIZE
+    } else {
+        base + MAP_SIZE
+    };
+    assert_eq!(frames.allocate_constrained(1, 2 * MAP_SIZE, phys(u64::MAX)), Some(frame(expected)));
+    assert_eq!(frames.allocate_constrained(1, 2 * MAP_SIZE, phys(u64::MAX)), None);
+    assert_eq!(frames.allocate_constrained(1, 3 * PAGE_SIZE, phys(u64::MAX)), None);
+}
+
+#[test]
+fn allocator_skips_full_maps() {
+    let base = new_region(2);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 2 * Buddy::PAGE_COUNT) };
+    assert!(frames.mark(frame(base), Buddy::PAGE_COUNT));
+
+    let full = frames.map_mut(phys(base)).unwrap();
+    assert!(full.free_counts.iter().all(|count| *count == 0));
+
+    // a full map must be skipped on its counts alone, so pretend its bitmap
+    // is all free
+    full.bitmap[..layers_size(MAX_ORDER)].fill(0);
+
+    assert_eq!(frames.allocate(1), Some(frame(base + MAP_SIZE)));
+}
+
+#[test]
+fn allocator_skips_fragmented_maps() {
+    let base = new_region(2);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 2 * Buddy::PAGE_COUNT) };
+
+    // leave every other page of the first map free
+    for page in (0..Buddy::PAGE_COUNT as u64).step_by(2) {
+        assert!(frames.mark(frame(base + page * PAGE_SIZE), 1));
+    }
+
+    let fragmented = frames.map_mut(phys(base)).unwrap();
+    assert_eq!(fragmented.free_counts[0] as usize, Buddy::PAGE_COUNT / 2);
+    assert!(fragmented.free_counts[1..].iter().all(|count| *count == 0));
+
+    assert_eq!(frames.allocate(2), Some(frame(base + MAP_SIZE)));
+    assert_eq!(frames.allocate(1), Some(frame(base + PAGE_SIZE)));
+}
+
+#[test]
+fn allocator_never_visits_unusable_maps() {
+    let base = new_region(3);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 3 * Buddy::PAGE_COUNT) };
+
+    // fill the first map, and leave only single pages free in the second
+    assert!(frames.mark(frame(base), Buddy::PAGE_COUNT));
+    for page in (0..Buddy::PAGE_COUNT as u64).step_by(2) {
+        assert!(frames.mark(frame(base + MAP_SIZE + page * PAGE_SIZE), 1));
+    }
+
+    // were either map visited, its bitmap and counts would hand out its
+    // first block
+    for map_base in [base, base + MAP_SIZE] {
+        let map = frames.map_mut(phys(map_base)).unwrap();
+        map.bitmap[..layers_size(MAX_ORDER)].fill(0);
+        map.free_counts = [1; MAX_LAYERS];
+    }
+
+    assert_eq!(frames.allocate(2), Some(frame(base + 2 * MAP_SIZE)));
+}
+
+#[test]
+fn allocator_relinks_freed_maps() {
+    let base = new_region(2);
+    let mut frames = FrameAllocator::new();
+
+    unsafe { frames.add_region(frame(base), 2 * Buddy::PAGE_COUNT) };
+    assert!(frames.mark(frame(base + MAP_SIZE), Buddy::PAGE_COUNT));
+
+    // the first page of the first map holds the maps
+    let pages: Vec<_> = core::iter::from_fn(|| frames.allocate(1)).collect();
+    assert_eq!(pages.len(), Buddy::PAGE_COUNT - 1);
+
+    assert_eq!(frames.free(pages[10], 1), Ok(()));
+    assert_eq!(frames.allocate(2), None);
+    assert_eq!(frames.allocate(1), Some(pages[10]));
+}
+
+#[test]
+#[should_panic(expected = "double free of page")]
+fn checked_allocator_panics() {
+    let base = new_region(1);
+    let mut frames = FrameAllocator::new_checked();
+
+    unsafe { frames.add_region(frame(base), Buddy::PAGE_COUNT) };
+
+    let page = frames.allocate(1).unwrap().start_address().as_u64();
+    let _ = frames.free(frame(page), 1);
+    let _ = frames.free(frame(page), 1);
+}
+
+/// Minimal xorshift generator, to keep the randomized tests reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, limit: usize) -> usize {
+        (self.next() % limit as u64) as usize
+    }
+}
+
+/// Check that the map agrees with the reference bitmap, that every free page
+/// belongs to exactly one free block, and that the free block counts match
+/// the bitmap.
+fn assert_matches<const ORDER: usize, const BITMAP_SIZE: usize>(
+    buddy: &BuddyMap<ORDER, BITMAP_SIZE>,
+    model: &[bool],
+) {
+    for (offset, free) in model.iter().enumerate() {
+        let free_blocks = (0..=ORDER)
+            .filter(|layer| buddy.check_layer(offset, *layer))
+            .count();
+
+        assert_eq!(free_blocks, *free as usize, "page offset {}", offset);
+    }
+
+    for layer in 0..=ORDER {
+        let free_blocks = (0..model.len())
+            .step_by(1 << layer)
+            .filter(|offset| buddy.check_layer(*offset, layer))
+            .count();
+
+        assert_eq!(buddy.free_counts[layer] as usize, free_blocks, "layer {}", layer);
+    }
+}
+
+/// Run random operations against a fresh map and a reference bitmap, adding
+/// and marking ranges of up to `max_range` pages at a time.
+fn run_model<const ORDER: usize, const BITMAP_SIZE: usize>(rng: &mut Rng, max_range: usize) {
+    const BASE: u64 = 0x8000_0000;
+
+    let page_count = BuddyMap::<ORDER, BITMAP_SIZE>::PAGE_COUNT;
+    let buddy = new_map::<ORDER, BITMAP_SIZE>(BASE);
+    let mut model = vec![false; page_count];
+    let mut allocated = vec![false; page_count];
+    let mut allocations = Vec::new();
+
+    for _ in 0..1000 {
+        match rng.below(4) {
+            0 => {
+                let offset = rng.below(page_count);
+                let count = rng.below(max_range) + 1;
+                buddy.add_free(frame(BASE + (offset as u64) * 0x1000), count);
+
+                let end = (offset + count).min(page_count);
+                model[offset..end].iter_mut().for_each(|page| *page = true);
+                allocated[offset..end].iter_mut().for_each(|page| *page = false);
+            },
+            1 => {
+                let offset = rng.below(page_count);
+                let count = rng.below(max_range) + 1;
+                buddy.mark(frame(BASE + (offset as u64) * 0x1000), count);
+
+                let end = (offset + count).min(page_count);
+                model[offset..end].iter_mut().for_each(|page| *page = false);
+            },
+            2 => {
+                if allocations.is_empty() {
+                    continue;
+                }
+
+                // pages may have been added or marked since they were allocated
+                let (offset, count): (usize, usize) = allocations.swap_remove(rng.below(allocations.len()));
+                let expected = (offset..(offset + count))
+                    .find_map(|page| {
+                        let address = BASE + (page as u64) * 0x1000;
+                        if model[page] {
+                            Some(FreeError::DoubleFree(phys(address)))
+                        } else if !allocated[page] {
+                            Some(FreeError::NotAllocated(phys(address)))
+                        } else {
+                            None
+                        }
+                    })
+                    .map_or(Ok(()), Err);
+
+                assert_eq!(buddy.free(frame(BASE + (offset as u64) * 0x1000), count), expected);
+
+                if expected.is_ok() {
+                    model[offset..(offset + count)].iter_mut().for_each(|page| *page = true);
+                    allocated[offset..(offset + count)].iter_mut().for_each(|page| *page = false);
+                }
+            },
+            _ => {
+                let count = rng.below(1 << ORDER) + 1;
+                let block_size = count.next_power_of_two();
+
+                // the first free naturally aligned block, if any
+                let expected = (0..page_count)
+                    .step_by(block_size)
+                    .find(|offset| model[*offset..(offset + block_size)].iter().all(|page| *page));
+
+                match buddy.allocate(count) {
+                    Some(address) => {
+                        let offset = ((address.start_address() - phys(BASE)) / 0x1000) as usize;
+
+                        assert_eq!(offset % block_size, 0);
+                        assert!(model[offset..(offset + block_size)].iter().all(|page| *page));
+                        model[offset..(offset + count)].iter_mut().for_each(|page| *page = false);
+                        allocated[offset..(offset + count)].iter_mut().for_each(|page| *page = true);
+                        allocations.push((offset, count));
+                    },
+                    None => assert_eq!(expected, None),
+                }
+            },
+        }
+
+        assert_matches(buddy, &model);
+    }
+}
+
+#[test]
+fn randomized_against_model() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..8 {
+        run_model::<3, { bitmap_size(3) }>(&mut rng, 16);
+    }
+
+    run_model::<{ MAX_ORDER }, { bitmap_size(MAX_ORDER) }>(&mut rng, 1024);
+}
+
+// The only test touching the global allocator, as tests run concurrently.
+#[test]
+fn owned_frames_free_on_drop() {
+    let base = new_region(1);
+    unsafe { FRAME_ALLOCATOR.lock().add_region(frame(base), Buddy::PAGE_COUNT) };
+
+    let free = FRAME_ALLOCATOR.lock().stats().free;
+
+    let owned = PhysFrame::allocate().unwrap();
+    assert_eq!(owned.address(), owned.frame().start_address());
+
+    let range = FrameRange::allocate_constrained(3, 4 * PAGE_SIZE, phys(u64::MAX)).unwrap();
+    assert!(range.start().is_aligned(4 * PAGE_SIZE));
+    assert_eq!(range.end(), range.start() + 3 * PAGE_SIZE);
+    assert_eq!(FRAME_ALLOCATOR.lock().stats().free, free - 4);
+
+    drop(owned);
+    drop(range);
+    assert_eq!(FRAME_ALLOCATOR.lock().stats().free, free);
+
+    let leaked = PhysFrame::allocate().unwrap().leak();
+    assert_eq!(FRAME_ALLOCATOR.lock().stats().free, free - 1);
+    assert_eq!(FRAME_ALLOCATOR.lock().free(leaked, 1), Ok(()));
+}
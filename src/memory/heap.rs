@@ -0,0 +1,191 @@
+//! Kernel heap.
+//!
+//! Small allocations are served from size classes of 16 to 2048 bytes, each
+//! keeping a list of free objects carved out of whole pages. Anything larger
+//! is allocated as pages directly from the frame allocator, up to the size of
+//! its largest block (`MAX_SIZE`, 2 MiB). Larger allocations always fail.
+//!
+//! Pages are accessed through the direct map of physical memory. Pages taken
+//! for size classes are never returned to the frame allocator.
+
+#[cfg(test)]
+#[path = "heap_tests.rs"]
+mod heap_tests;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+use crate::sync::IrqMutex;
+
+use super::{Frame, PAGE_SIZE, PhysAddr, VirtAddr, phys_to_virt, virt_to_phys};
+use super::frame::{FrameAllocator, FRAME_ALLOCATOR, MAX_ORDER};
+
+const MIN_CLASS_SHIFT: usize = 4;
+const CLASS_COUNT: usize = 8;
+
+/// Largest object served from a size class.
+const MAX_CLASS_SIZE: usize = 1 << (MIN_CLASS_SHIFT + CLASS_COUNT - 1);
+
+/// Largest allocation the heap can serve, a single block of the frame
+/// allocator.
+pub const MAX_SIZE: usize = (1 << MAX_ORDER) * PAGE_SIZE as usize;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// Size class allocator, taking pages from a `FrameAllocator`.
+pub struct Heap {
+    free_lists: [*mut FreeObject; CLASS_COUNT],
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Heap {
+            free_lists: [null_mut(); CLASS_COUNT],
+        }
+    }
+
+    /// Allocate memory for `layout`, returning null if out of memory, or if
+    /// `layout` is larger than `MAX_SIZE`.
+    pub fn allocate(&mut self, layout: Layout, frames: &mut FrameAllocator) -> *mut u8 {
+        match size_class(layout) {
+            Some(class) => self.allocate_object(class, frames),
+            None => frames
+                .allocate_constrained(page_count(layout), layout.align().max(PAGE_SIZE as usize) as u64, PhysAddr::new(u64::MAX))
+                .map_or(null_mut(), |frame| phys_to_virt(frame.start_address()).as_mut_ptr()),
+        }
+    }
+
+    /// Free memory previously returned by `allocate` for the same `layout`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated from this heap and `frames`, with the
+    /// same `layout`, and must not be used afterwards.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout, frames: &mut FrameAllocator) {
+        match size_class(layout) {
+            Some(class) => {
+                let object = ptr as *mut FreeObject;
+                (*object).next = self.free_lists[class];
+                self.free_lists[class] = object;
+            },
+            None => {
+                let frame = Frame::containing_address(virt_to_phys(VirtAddr::from_ptr(ptr)));
+                let result = frames.free(frame, page_count(layout));
+                debug_assert!(result.is_ok(), "invalid heap free of {:p}: {:?}", ptr, result);
+            },
+        }
+    }
+
+    fn allocate_object(&mut self, class: usize, frames: &mut FrameAllocator) -> *mut u8 {
+        if self.free_lists[class].is_null() {
+            self.refill(class, frames);
+        }
+
+        let object = self.free_lists[class];
+        if !object.is_null() {
+            // SAFETY: objects on the free lists are unused, and lie within
+            // pages owned by the heap
+            self.free_lists[class] = unsafe { (*object).next };
+        }
+
+        object as *mut u8
+    }
+
+    /// Carve a new page into objects of size `class`.
+    fn refill(&mut self, class: usize, frames: &mut FrameAllocator) {
+        let page = match frames.allocate(1) {
+            Some(frame) => phys_to_virt(frame.start_address()),
+            None => return,
+        };
+
+        let object_size = 1 << (MIN_CLASS_SHIFT + class);
+        // push in reverse, so objects are handed out in order of address
+        for offset in (0..PAGE_SIZE as usize).step_by(object_size).rev() {
+            let object = (page + offset as u64).as_mut_ptr::<FreeObject>();
+
+            // SAFETY: the page was just handed out by the frame allocator
+            unsafe { (*object).next = self.free_lists[class] };
+            self.free_lists[class] = object;
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY: the free lists only point into pages owned by the heap
+unsafe impl Send for Heap {}
+
+/// Size class of `layout`, or `None` if it is to be allocated as whole pages.
+///
+/// Objects are aligned to their size, so the alignment is covered by rounding
+/// up the size.
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size()
+        .max(layout.align())
+        .max(1 << MIN_CLASS_SHIFT)
+        .next_power_of_two();
+
+    if size > MAX_CLASS_SIZE {
+        return None;
+    }
+
+    Some(size.trailing_zeros() as usize - MIN_CLASS_SHIFT)
+}
+
+fn page_count(layout: Layout) -> usize {
+    layout.size().div_ceil(PAGE_SIZE as usize)
+}
+
+/// Kernel heap, taking pages from `FRAME_ALLOCATOR`.
+///
+/// Meant to be installed as the `#[global_allocator]`.
+pub struct KernelHeap {
+    heap: IrqMutex<Heap>,
+}
+
+impl KernelHeap {
+    pub const fn new() -> Self {
+        KernelHeap {
+            heap: IrqMutex::new(Heap::new()),
+        }
+    }
+}
+
+impl Default for KernelHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.lock().allocate(layout, &mut FRAME_ALLOCATOR.lock())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(ptr, layout, &mut FRAME_ALLOCATOR.lock())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+
+        // objects have room for anything else of the same size class
+        if size_class(layout).is_some() && size_class(layout) == size_class(new_layout) {
+            return ptr;
+        }
+
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+
+        new_ptr
+    }
+}
@@ -0,0 +1,120 @@
+use super::*;
+
+use crate::memory::test_util::new_frames;
+
+fn layout(size: usize, align: usize) -> Layout {
+    Layout::from_size_align(size, align).unwrap()
+}
+
+#[test]
+fn size_classes() {
+    assert_eq!(size_class(layout(1, 1)), Some(0));
+    assert_eq!(size_class(layout(16, 8)), Some(0));
+    assert_eq!(size_class(layout(24, 8)), Some(1));
+    assert_eq!(size_class(layout(8, 256)), Some(4));
+    assert_eq!(size_class(layout(2048, 8)), Some(7));
+    assert_eq!(size_class(layout(2049, 8)), None);
+    assert_eq!(size_class(layout(16, 4096)), None);
+}
+
+#[test]
+fn objects_share_pages() {
+    let mut frames = new_frames();
+    let mut heap = Heap::new();
+    let free = frames.stats().free;
+
+    let objects: Vec<_> = (0..(PAGE_SIZE as usize / 32 + 1))
+        .map(|_| heap.allocate(layout(24, 8), &mut frames))
+        .collect();
+
+    assert_eq!(frames.stats().free, free - 2);
+    for (index, object) in objects.iter().enumerate() {
+        assert_eq!(*object as usize % 32, 0);
+        unsafe { object.write_bytes(index as u8, 24) };
+    }
+
+    let mut addresses: Vec<_> = objects.iter().map(|object| *object as usize).collect();
+    addresses.sort();
+    addresses.dedup();
+    assert_eq!(addresses.len(), objects.len());
+
+    // no object was overwritten by its neighbours
+    for (index, object) in objects.iter().enumerate() {
+        let bytes = unsafe { core::slice::from_raw_parts(*object, 24) };
+        assert!(bytes.iter().all(|byte| *byte == index as u8));
+    }
+}
+
+#[test]
+fn freed_objects_are_reused() {
+    let mut frames = new_frames();
+    let mut heap = Heap::new();
+
+    let first = heap.allocate(layout(100, 8), &mut frames);
+    let second = heap.allocate(layout(128, 128), &mut frames);
+    assert_eq!(first as usize % 128, 0);
+    assert_ne!(first, second);
+
+    unsafe { heap.deallocate(first, layout(100, 8), &mut frames) };
+    assert_eq!(heap.allocate(layout(65, 1), &mut frames), first);
+}
+
+#[test]
+fn large_allocations_use_pages() {
+    let mut frames = new_frames();
+    let mut heap = Heap::new();
+    let free = frames.stats().free;
+
+    let block = heap.allocate(layout(3 * PAGE_SIZE as usize, 8), &mut frames);
+    assert_eq!(block as u64 % PAGE_SIZE, 0);
+    assert_eq!(frames.stats().free, free - 3);
+
+    let aligned = heap.allocate(layout(16, 16 * PAGE_SIZE as usize), &mut frames);
+    assert_eq!(aligned as u64 % (16 * PAGE_SIZE), 0);
+    assert_eq!(frames.stats().free, free - 4);
+
+    unsafe {
+        heap.deallocate(block, layout(3 * PAGE_SIZE as usize, 8), &mut frames);
+        heap.deallocate(aligned, layout(16, 16 * PAGE_SIZE as usize), &mut frames);
+    }
+    assert_eq!(frames.stats().free, free);
+}
+
+#[test]
+fn allocations_above_max_size_fail() {
+    let mut frames = new_frames();
+    let mut heap = Heap::new();
+    let free = frames.stats().free;
+
+    // plenty of memory is free, but no block is larger than MAX_SIZE
+    assert!(free * PAGE_SIZE as usize > 2 * MAX_SIZE);
+    assert!(heap.allocate(layout(MAX_SIZE + 1, 8), &mut frames).is_null());
+    assert_eq!(frames.stats().free, free);
+
+    let block = heap.allocate(layout(MAX_SIZE, 8), &mut frames);
+    assert!(!block.is_null());
+    unsafe { heap.deallocate(block, layout(MAX_SIZE, 8), &mut frames) };
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "invalid heap free")]
+fn invalid_large_free_panics() {
+    let mut frames = new_frames();
+    let mut heap = Heap::new();
+
+    let block = heap.allocate(layout(3 * PAGE_SIZE as usize, 8), &mut frames);
+    unsafe {
+        heap.deallocate(block, layout(3 * PAGE_SIZE as usize, 8), &mut frames);
+        heap.deallocate(block, layout(3 * PAGE_SIZE as usize, 8), &mut frames);
+    }
+}
+
+#[test]
+fn out_of_memory() {
+    let mut frames = FrameAllocator::new();
+    let mut heap = Heap::new();
+
+    assert!(heap.allocate(layout(8, 8), &mut frames).is_null());
+    assert!(heap.allocate(layout(8192, 8), &mut frames).is_null());
+}
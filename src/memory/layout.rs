@@ -0,0 +1,187 @@
+//! Kernel virtual memory layout.
+//!
+//! The upper 256 GiB of the Sv39 address space belong to the kernel. The
+//! bottom of it holds a direct map of physical memory, and the top 2 GiB the
+//! kernel image itself:
+//!
+//! ```text
+//! 0xFFFF_FFC0_0000_0000  direct map of physical memory (RW)
+//! 0xFFFF_FFFF_8000_0000  .text (RX), .rodata (R), .data and .bss (RW)
+//! ```
+
+#[cfg(test)]
+#[path = "layout_tests.rs"]
+mod layout_tests;
+
+use super::{PageSize, PhysAddr, Size1G, VirtAddr};
+use super::frame::FrameAllocator;
+use super::paging::{AddressSpace, MapError, PageTableFlags, PagingMode};
+
+/// Virtual address of physical address 0 in the direct map.
+pub const DIRECT_MAP_BASE: VirtAddr = VirtAddr::new(0xFFFF_FFC0_0000_0000);
+
+/// Virtual address of the start of the kernel image.
+pub const KERNEL_BASE: VirtAddr = VirtAddr::new(0xFFFF_FFFF_8000_0000);
+
+/// Largest amount of physical memory covered by the direct map.
+pub const DIRECT_MAP_SIZE: u64 = KERNEL_BASE.as_u64() - DIRECT_MAP_BASE.as_u64();
+
+/// Physical location of the sections of the kernel image.
+///
+/// All boundaries are page aligned, see `linker.ld`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KernelImage {
+    pub start: PhysAddr,
+    pub text_end: PhysAddr,
+    pub rodata_end: PhysAddr,
+    pub end: PhysAddr,
+}
+
+impl KernelImage {
+    /// Locate the running kernel image through the symbols of the linker
+    /// script.
+    ///
+    /// The symbols are resolved relative to the program counter, so this
+    /// returns physical addresses as long as paging is disabled or identity
+    /// mapped.
+    #[cfg(target_arch = "riscv64")]
+    pub fn current() -> Self {
+        let (start, text_end, rodata_end, end): (u64, u64, u64, u64);
+
+        // SAFETY: only computes addresses
+        unsafe {
+            asm!(
+                "lla {0}, __kernel_start",
+                "lla {1}, __text_end",
+                "lla {2}, __rodata_end",
+                "lla {3}, __kernel_end",
+                out(reg) start,
+                out(reg) text_end,
+                out(reg) rodata_end,
+                out(reg) end,
+            );
+        }
+
+        KernelImage {
+            start: PhysAddr::new(start),
+            text_end: PhysAddr::new(text_end),
+            rodata_end: PhysAddr::new(rodata_end),
+            end: PhysAddr::new(end),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Higher half virtual address of `address` within the image.
+    pub fn to_virt(&self, address: PhysAddr) -> VirtAddr {
+        KERNEL_BASE + (address - self.start)
+    }
+}
+
+/// Virtual address of `address` in the direct map, once it is active.
+fn direct_map(address: PhysAddr) -> VirtAddr {
+    DIRECT_MAP_BASE + address.as_u64()
+}
+
+/// Build the kernel address space for `image`, with a direct map covering
+/// physical memory up to `memory_end`.
+///
+/// The image is mapped with W^X permissions in the higher half only, and its
+/// pages are read-only in the direct map.
+pub fn build_kernel_space(
+    image: &KernelImage,
+    memory_end: PhysAddr,
+    frames: &mut FrameAllocator,
+) -> Result<AddressSpace, MapError> {
+    let mut space = AddressSpace::new(PagingMode::Sv39, frames).ok_or(MapError::OutOfMemory)?;
+
+    let global = PageTableFlags::GLOBAL | PageTableFlags::ACCESSED;
+    let read = global | PageTableFlags::READ;
+    let read_write = read | PageTableFlags::WRITE | PageTableFlags::DIRTY;
+    let read_execute = read | PageTableFlags::EXECUTE;
+
+    // the direct map extends to a whole gigapage, which covers any MMIO
+    // regions between memory regions as well
+    let direct_end = memory_end
+        .align_up(Size1G::SIZE)
+        .min(PhysAddr::new(DIRECT_MAP_SIZE))
+        .max(image.end);
+    let zero = PhysAddr::zero();
+
+    space.map_range(direct_map(zero), zero, image.start - zero, read_write, frames)?;
+    space.map_range(direct_map(image.start), image.start, image.size(), read, frames)?;
+    space.map_range(direct_map(image.end), image.end, direct_end - image.end, read_write, frames)?;
+
+    let sections = [
+        (image.start, image.text_end, read_execute),
+        (image.text_end, image.rodata_end, read),
+        (image.rodata_end, image.end, read_write),
+    ];
+
+    for &(start, end, flags) in &sections {
+        space.map_range(image.to_virt(start), start, end - start, flags, frames)?;
+    }
+
+    Ok(space)
+}
+
+/// Activate `space`, and continue in `entry` running from the higher half.
+///
+/// The stack is moved into the direct map, and `argument` is passed on to
+/// `entry`. Physical memory is only reachable through `phys_to_virt` from
+/// then on, and statics holding pointers must be relocated by `entry` before
+/// use.
+///
+/// As the image is no longer mapped at its physical address, fetching the
+/// instruction following the write to satp faults. The trap vector points at
+/// the higher half address of that instruction, so execution resumes there.
+///
+/// Before jumping to `entry`, the trap vector is pointed at a loop halting
+/// the hart, so any trap taken before `entry` installs its own trap vector
+/// stops the hart, instead of running the trampoline again.
+///
+/// # Safety
+///
+/// `space` must have been built by `build_kernel_space` for `image`. The
+/// current stack must lie in physical memory covered by the direct map, and
+/// must not be referred to by anything `entry` uses.
+#[cfg(target_arch = "riscv64")]
+pub unsafe fn enter(
+    space: &AddressSpace,
+    image: &KernelImage,
+    entry: extern "C" fn(usize) -> !,
+    argument: usize,
+) -> ! {
+    super::set_direct_map_offset(DIRECT_MAP_BASE);
+
+    // Operands are pinned to registers, leaving t0 free as scratch, as
+    // outputs can not be declared with `noreturn`.
+    asm!(
+        "lla t0, 1f",
+        "add t0, t0, a1",
+        "csrw stvec, t0",
+        "sfence.vma",
+        "csrw satp, a2",
+        // stvec requires 4 byte alignment
+        ".balign 4",
+        "1:",
+        "sfence.vma",
+        // the trampoline must only run once, so halt on any further trap
+        "lla t0, 2f",
+        "csrw stvec, t0",
+        "add sp, sp, a3",
+        "jr a4",
+        ".balign 4",
+        "2:",
+        "wfi",
+        "j 2b",
+        in("a0") argument,
+        in("a1") KERNEL_BASE.as_u64().wrapping_sub(image.start.as_u64()),
+        in("a2") space.satp(),
+        in("a3") DIRECT_MAP_BASE.as_u64(),
+        in("a4") image.to_virt(PhysAddr::new(entry as usize as u64)).as_u64(),
+        options(noreturn),
+    );
+}
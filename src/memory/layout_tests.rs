@@ -0,0 +1,86 @@
+use super::*;
+
+use crate::memory::test_util::{new_frames, phys};
+
+fn image() -> KernelImage {
+    KernelImage {
+        start: phys(0x8020_0000),
+        text_end: phys(0x8024_0000),
+        rodata_end: phys(0x8025_3000),
+        end: phys(0x8026_0000),
+    }
+}
+
+fn permissions(space: &AddressSpace, address: VirtAddr) -> Option<PageTableFlags> {
+    space.flags(address).map(|flags| {
+        flags & (PageTableFlags::READ | PageTableFlags::WRITE | PageTableFlags::EXECUTE)
+    })
+}
+
+#[test]
+fn image_sections() {
+    let mut frames = new_frames();
+    let image = image();
+    let space = build_kernel_space(&image, phys(0x8800_0000), &mut frames).unwrap();
+
+    let rx = PageTableFlags::READ | PageTableFlags::EXECUTE;
+    let r = PageTableFlags::READ;
+    let rw = PageTableFlags::READ | PageTableFlags::WRITE;
+
+    assert_eq!(permissions(&space, KERNEL_BASE), Some(rx));
+    assert_eq!(permissions(&space, KERNEL_BASE + 0x3_FFFF), Some(rx));
+    assert_eq!(permissions(&space, KERNEL_BASE + 0x4_0000), Some(r));
+    assert_eq!(permissions(&space, KERNEL_BASE + 0x5_2FFF), Some(r));
+    assert_eq!(permissions(&space, KERNEL_BASE + 0x5_3000), Some(rw));
+    assert_eq!(permissions(&space, KERNEL_BASE + 0x5_FFFF), Some(rw));
+
+    // nothing is left at the load address
+    assert_eq!(space.translate(VirtAddr::new(image.start.as_u64())), None);
+
+    assert_eq!(space.translate(KERNEL_BASE), Some(phys(0x8020_0000)));
+    assert_eq!(space.translate(KERNEL_BASE + 0x5_3123), Some(phys(0x8025_3123)));
+    assert_eq!(space.translate(KERNEL_BASE + 0x6_0000), None);
+    assert_eq!(image.to_virt(phys(0x8024_1000)), KERNEL_BASE + 0x4_1000);
+
+    let flags = space.flags(KERNEL_BASE).unwrap();
+    assert!(flags.contains(PageTableFlags::GLOBAL | PageTableFlags::ACCESSED));
+    assert!(!flags.contains(PageTableFlags::USER));
+}
+
+#[test]
+fn direct_map() {
+    let mut frames = new_frames();
+    let image = image();
+    let space = build_kernel_space(&image, phys(0x8800_0000), &mut frames).unwrap();
+
+    let rw = PageTableFlags::READ | PageTableFlags::WRITE;
+
+    assert_eq!(space.translate(DIRECT_MAP_BASE), Some(phys(0)));
+    assert_eq!(space.translate(DIRECT_MAP_BASE + 0x1000_0000), Some(phys(0x1000_0000)));
+    assert_eq!(space.translate(DIRECT_MAP_BASE + 0x8765_4321), Some(phys(0x8765_4321)));
+    assert_eq!(permissions(&space, DIRECT_MAP_BASE + 0x1000_0000), Some(rw));
+    assert_eq!(permissions(&space, DIRECT_MAP_BASE + 0x9000_0000), Some(rw));
+
+    // the image is read-only through the direct map
+    assert_eq!(permissions(&space, DIRECT_MAP_BASE + 0x8020_0000), Some(PageTableFlags::READ));
+    assert_eq!(permissions(&space, DIRECT_MAP_BASE + 0x8025_F000), Some(PageTableFlags::READ));
+    assert_eq!(permissions(&space, DIRECT_MAP_BASE + 0x801F_F000), Some(rw));
+    assert_eq!(permissions(&space, DIRECT_MAP_BASE + 0x8026_0000), Some(rw));
+
+    // rounded up to the next gigapage, and nothing else in the lower half
+    assert_eq!(space.translate(DIRECT_MAP_BASE + 0xBFFF_FFFF), Some(phys(0xBFFF_FFFF)));
+    assert_eq!(space.translate(DIRECT_MAP_BASE + 0xC000_0000), None);
+    assert_eq!(space.translate(VirtAddr::new(0x1000_0000)), None);
+}
+
+#[test]
+fn direct_map_size_is_limited() {
+    let mut frames = new_frames();
+    let space = build_kernel_space(&image(), phys(u64::MAX / 2), &mut frames).unwrap();
+
+    assert_eq!(
+        space.translate(DIRECT_MAP_BASE + DIRECT_MAP_SIZE - 1),
+        Some(phys(DIRECT_MAP_SIZE - 1))
+    );
+    assert_eq!(space.translate(KERNEL_BASE), Some(phys(0x8020_0000)));
+}
@@ -0,0 +1,496 @@
+//! Physical memory map.
+//!
+//! Firmware describes memory in its own terms, either as a UEFI memory map or
+//! through the device tree. Both are normalized into a sorted list of
+//! non-overlapping `MemoryRegion`s, each of a single `MemoryKind`.
+
+#[cfg(test)]
+#[path = "map_tests.rs"]
+mod map_tests;
+
+use mercuros_uefi::{
+    MemoryMap,
+    api::boot_services::memory::{
+        EFI_LOADER_DATA,
+        EFI_BOOT_SERVICES_CODE,
+        EFI_BOOT_SERVICES_DATA,
+        EFI_RUNTIME_SERVICES_CODE,
+        EFI_RUNTIME_SERVICES_DATA,
+        EFI_CONVENTIONAL_MEMORY,
+    },
+};
+
+use crate::fdt::Fdt;
+
+use super::{Frame, PAGE_SIZE, PhysAddr};
+
+/// Maximum number of regions tracked by a `MemoryRegions` list.
+pub const MAX_REGIONS: usize = 128;
+
+/// Maximum number of ranges tracked by a `ReservedRanges` list.
+pub const MAX_RESERVED: usize = 16;
+
+/// Use of a region of physical memory.
+///
+/// Where regions overlap, the later kind in this list takes precedence.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum MemoryKind {
+    /// Free for the kernel to use.
+    Usable,
+    /// Used by firmware at runtime, e.g. UEFI runtime services.
+    Firmware,
+    /// Not to be touched by the kernel.
+    Reserved,
+}
+
+impl MemoryKind {
+    /// Classify a UEFI memory type.
+    ///
+    /// Memory used by the bootloader and boot services is usable once the
+    /// kernel runs, except for anything listed in `ReservedRanges`.
+    pub fn from_uefi(memory_type: u32) -> Self {
+        match memory_type {
+            EFI_LOADER_DATA |
+            EFI_BOOT_SERVICES_CODE |
+            EFI_BOOT_SERVICES_DATA |
+            EFI_CONVENTIONAL_MEMORY => MemoryKind::Usable,
+            EFI_RUNTIME_SERVICES_CODE |
+            EFI_RUNTIME_SERVICES_DATA => MemoryKind::Firmware,
+            _ => MemoryKind::Reserved,
+        }
+    }
+}
+
+/// Page aligned range of physical memory.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryRegion {
+    pub start: Frame,
+    pub pages: usize,
+    pub kind: MemoryKind,
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> PhysAddr {
+        self.start.start_address() + self.pages as u64 * PAGE_SIZE
+    }
+}
+
+/// Fixed capacity list of physical memory regions.
+///
+/// Regions are added in any order, and may overlap until the list is
+/// normalized. A region adjacent to the last one added, and of the same kind,
+/// extends it, and a full list is normalized to make room. Regions which
+/// still do not fit are dropped, and counted by `dropped`. Adding a region
+/// other than `Usable` removes it from any usable regions first, so this only
+/// ever loses usable memory, and never causes reserved memory to be handed
+/// out.
+pub struct MemoryRegions {
+    regions: [MemoryRegion; MAX_REGIONS],
+    len: usize,
+    dropped: usize,
+    // no regions were added since the last `normalize`
+    normalized: bool,
+}
+
+impl MemoryRegions {
+    pub const fn new() -> Self {
+        MemoryRegions {
+            regions: [MemoryRegion { start: Frame::zero(), pages: 0, kind: MemoryKind::Reserved }; MAX_REGIONS],
+            len: 0,
+            dropped: 0,
+            normalized: true,
+        }
+    }
+
+    /// Derive the memory map from a UEFI memory map.
+    pub fn from_uefi(map: &MemoryMap) -> Self {
+        Self::from_ranges(map.into_iter().map(|descriptor| (
+            PhysAddr::new(descriptor.physical_start),
+            descriptor.number_of_pages * PAGE_SIZE,
+            MemoryKind::from_uefi(descriptor.r#type),
+        )))
+    }
+
+    /// Derive the memory map from a list of `(address, size, kind)` ranges,
+    /// as described by firmware.
+    pub fn from_ranges<I>(ranges: I) -> Self
+    where
+        I: IntoIterator<Item = (PhysAddr, u64, MemoryKind)>,
+    {
+        let mut regions = MemoryRegions::new();
+
+        for (address, size, kind) in ranges {
+            regions.add(address, size, kind);
+        }
+
+        regions.normalize();
+
+        regions
+    }
+
+    /// Derive the memory map from the device tree.
+    ///
+    /// This covers all `/memory` nodes, with any `/reserved-memory` regions
+    /// and entries of the memory reservation block marked as reserved.
+    pub fn from_fdt(fdt: &Fdt<'_>) -> Self {
+        let mut regions = MemoryRegions::new();
+
+        let memory_nodes = fdt.root()
+            .into_iter()
+            .flat_map(|root| root.children())
+            .filter(|node| {
+                node.is_enabled() && node.property("device_type")
+                    .and_then(|property| property.as_str()) == Some("memory")
+            });
+
+        for node in memory_nodes {
+            for reg in node.regions().into_iter().flatten() {
+                regions.add(PhysAddr::new(reg.address), reg.size, MemoryKind::Usable);
+            }
+        }
+
+        regions.add_fdt_reservations(fdt);
+
+        regions
+    }
+
+    /// Add the memory range `[address, address + size)` of `kind`.
+    ///
+    /// Partial pages at either end of a usable range are skipped, while other
+    /// ranges are extended to whole pages. The list is left unsorted, see
+    /// `normalize`.
+    pub fn add(&mut self, address: PhysAddr, size: u64, kind: MemoryKind) {
+        let address = address.as_u64();
+        let (start, end) = match kind {
+            MemoryKind::Usable => match address.checked_add(PAGE_SIZE - 1) {
+                Some(start) => (start & !(PAGE_SIZE - 1), address.saturating_add(size) & !(PAGE_SIZE - 1)),
+                None => return,
+            },
+            _ => (
+                address & !(PAGE_SIZE - 1),
+                address.saturating_add(size).saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            ),
+        };
+
+        if start >= end {
+            return;
+        }
+
+        // cutting splits at most one region of a normalized list, so this
+        // leaves room for both halves and the new region
+        if self.len + 2 > MAX_REGIONS && !self.normalized {
+            self.normalize();
+        }
+        self.normalized = false;
+
+        let (start, end) = (PhysAddr::new(start), PhysAddr::new(end));
+        if kind != MemoryKind::Usable {
+            self.cut(start, end, |region| region.kind == MemoryKind::Usable);
+        }
+
+        self.push_merged(start, end, kind);
+    }
+
+    /// Remove the memory range `[address, address + size)` from the map
+    /// altogether.
+    ///
+    /// Any page overlapping the range is removed, even if only partially.
+    pub fn remove(&mut self, address: PhysAddr, size: u64) {
+        let start = address.align_down(PAGE_SIZE);
+        let end = address.as_u64().saturating_add(size).saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        self.cut(start, PhysAddr::new(end), |_| true);
+    }
+
+    /// Mark all memory reserved by the device tree, through either the
+    /// memory reservation block or `/reserved-memory`, as reserved.
+    pub fn add_fdt_reservations(&mut self, fdt: &Fdt<'_>) {
+        for reservation in fdt.memory_reservations() {
+            self.add(PhysAddr::new(reservation.address), reservation.size, MemoryKind::Reserved);
+        }
+
+        let reserved_nodes = fdt.find_node("/reserved-memory")
+            .into_iter()
+            .flat_map(|reserved_memory| reserved_memory.children());
+
+        // Nodes with only a size describe memory to be allocated dynamically,
+        // which is left to the kernel.
+        for node in reserved_nodes {
+            for reg in node.regions().into_iter().flatten() {
+                self.add(PhysAddr::new(reg.address), reg.size, MemoryKind::Reserved);
+            }
+        }
+
+        self.normalize();
+    }
+
+    /// Mark all ranges in `reserved` as reserved.
+    pub fn add_reserved(&mut self, reserved: &ReservedRanges) {
+        for range in reserved {
+            self.add(range.start, range.end - range.start, MemoryKind::Reserved);
+        }
+
+        self.normalize();
+    }
+
+    /// Sort the regions by address, and resolve any overlaps in favour of
+    /// the kind taking precedence. Adjacent regions of the same kind are
+    /// merged.
+    pub fn normalize(&mut self) {
+        let mut boundaries = [PhysAddr::zero(); 2 * MAX_REGIONS];
+        for (index, region) in self.iter().enumerate() {
+            boundaries[2 * index] = region.start.start_address();
+            boundaries[2 * index + 1] = region.end();
+        }
+
+        let boundaries = &mut boundaries[..(2 * self.len)];
+        boundaries.sort_unstable();
+
+        // each span between consecutive boundaries is either fully covered
+        // by a region, or not at all
+        let mut normalized = MemoryRegions::new();
+        for span in boundaries.windows(2) {
+            let (start, end) = (span[0], span[1]);
+
+            let kind = self.iter()
+                .filter(|region| region.start.start_address() <= start && region.end() >= end)
+                .map(|region| region.kind)
+                .max();
+
+            if let (Some(kind), true) = (kind, start < end) {
+                normalized.push_merged(start, end, kind);
+            }
+        }
+
+        normalized.dropped += self.dropped;
+        *self = normalized;
+    }
+
+    /// Iterate over the regions of usable memory.
+    pub fn usable(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter().filter(|region| region.kind == MemoryKind::Usable)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of regions dropped, as they did not fit in the list.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, MemoryRegion> {
+        self.regions[..self.len].iter()
+    }
+
+    /// Remove `[start, end)` from the regions accepted by `filter`, keeping
+    /// whatever is left at either side.
+    fn cut<F>(&mut self, start: PhysAddr, end: PhysAddr, filter: F)
+    where
+        F: Fn(&MemoryRegion) -> bool,
+    {
+        if start >= end {
+            return;
+        }
+
+        let mut index = 0;
+        while index < self.len {
+            let region = self.regions[index];
+
+            if region.end() <= start || region.start.start_address() >= end || !filter(&region) {
+                index += 1;
+                continue;
+            }
+
+            self.swap_remove(index);
+
+            if region.start.start_address() < start {
+                self.push(MemoryRegion {
+                    pages: ((start - region.start.start_address()) / PAGE_SIZE) as usize,
+                    ..region
+                });
+            }
+
+            if region.end() > end {
+                self.push(MemoryRegion {
+                    start: Frame::containing_address(end),
+                    pages: ((region.end() - end) / PAGE_SIZE) as usize,
+                    ..region
+                });
+            }
+
+            // swap_remove moved an unvisited region into index, and any
+            // pushed remainders no longer overlap, so index is not advanced
+        }
+    }
+
+    fn push(&mut self, region: MemoryRegion) {
+        if self.len < MAX_REGIONS {
+            self.regions[self.len] = region;
+            self.len += 1;
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    /// Append `[start, end)`, extending the last region if adjacent and of
+    /// the same kind.
+    fn push_merged(&mut self, start: PhysAddr, end: PhysAddr, kind: MemoryKind) {
+        let pages = ((end - start) / PAGE_SIZE) as usize;
+
+        if let Some(last) = self.regions[..self.len].last_mut() {
+            if last.end() == start && last.kind == kind {
+                last.pages += pages;
+                return;
+            }
+        }
+
+        self.push(MemoryRegion { start: Frame::containing_address(start), pages, kind });
+    }
+
+    fn swap_remove(&mut self, index: usize) {
+        self.len -= 1;
+        self.regions[index] = self.regions[self.len];
+    }
+}
+
+impl Default for MemoryRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> IntoIterator for &'a MemoryRegions {
+    type Item = &'a MemoryRegion;
+    type IntoIter = core::slice::Iter<'a, MemoryRegion>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// What a reserved range of memory holds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Reservation {
+    Kernel,
+    Stack,
+    DeviceTree,
+    UefiMemoryMap,
+    Initrd,
+}
+
+/// Range of physical memory in use since before the kernel started, which
+/// must be kept out of the frame allocator.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ReservedRange {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+    pub reservation: Reservation,
+}
+
+/// Fixed capacity list of reserved memory ranges.
+///
+/// Unlike `MemoryRegions`, ranges are never dropped, as that would allow
+/// memory in use to be handed out. Once the list is full, ranges are widened
+/// to cover new ones instead, at the cost of also reserving some free memory.
+pub struct ReservedRanges {
+    ranges: [ReservedRange; MAX_RESERVED],
+    len: usize,
+}
+
+impl ReservedRanges {
+    pub const fn new() -> Self {
+        ReservedRanges {
+            ranges: [ReservedRange {
+                start: PhysAddr::zero(),
+                end: PhysAddr::zero(),
+                reservation: Reservation::Kernel,
+            }; MAX_RESERVED],
+            len: 0,
+        }
+    }
+
+    /// Reserve the memory range `[address, address + size)`.
+    ///
+    /// The range is merged into an existing one of the same reservation if
+    /// they overlap or touch. If the list is full, the nearest existing range
+    /// is widened to cover it, along with any memory in between.
+    pub fn add(&mut self, reservation: Reservation, address: PhysAddr, size: u64) {
+        if size == 0 {
+            return;
+        }
+
+        let start = address;
+        let end = PhysAddr::new(address.as_u64().saturating_add(size));
+
+        let touching = self.ranges[..self.len].iter_mut().find(|range| {
+            range.reservation == reservation && range.start <= end && start <= range.end
+        });
+        if let Some(range) = touching {
+            range.start = range.start.min(start);
+            range.end = range.end.max(end);
+            return;
+        }
+
+        if self.len == MAX_RESERVED {
+            let gap = |range: &ReservedRange| {
+                if end < range.start {
+                    range.start - end
+                } else {
+                    start.as_u64().saturating_sub(range.end.as_u64())
+                }
+            };
+
+            if let Some(range) = self.ranges.iter_mut().min_by_key(|range| gap(range)) {
+                range.start = range.start.min(start);
+                range.end = range.end.max(end);
+            }
+            return;
+        }
+
+        self.ranges[self.len] = ReservedRange { start, end, reservation };
+        self.len += 1;
+    }
+
+    /// Reserve the device tree blob at `address`, along with the initrd it
+    /// refers to, if any.
+    pub fn add_fdt(&mut self, fdt: &Fdt<'_>, address: PhysAddr) {
+        self.add(Reservation::DeviceTree, address, fdt.total_size() as u64);
+
+        if let Some((start, end)) = fdt.initrd() {
+            self.add(Reservation::Initrd, PhysAddr::new(start), end.saturating_sub(start));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, ReservedRange> {
+        self.ranges[..self.len].iter()
+    }
+}
+
+impl Default for ReservedRanges {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> IntoIterator for &'a ReservedRanges {
+    type Item = &'a ReservedRange;
+    type IntoIter = core::slice::Iter<'a, ReservedRange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
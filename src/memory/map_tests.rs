@@ -0,0 +1,302 @@
+use super::*;
+
+use mercuros_uefi::api::boot_services::memory::{EFI_LOADER_CODE, EFI_RESERVED_MEMORY_TYPE};
+
+use crate::memory::test_util::phys;
+
+static QEMU_VIRT: &[u8] = include_bytes!("../../tests/fixtures/qemu-virt.dtb");
+static QEMU_VIRT_OPENSBI: &[u8] = include_bytes!("../../tests/fixtures/qemu-virt-opensbi.dtb");
+static HIFIVE_UNMATCHED: &[u8] = include_bytes!("../../tests/fixtures/hifive-unmatched-a00.dtb");
+
+/// Usable regions, sorted by address.
+fn sorted(regions: &MemoryRegions) -> Vec<(u64, usize)> {
+    let mut regions: Vec<_> = regions.usable()
+        .map(|region| (region.start.start_address().as_u64(), region.pages))
+        .collect();
+    regions.sort();
+
+    regions
+}
+
+#[test]
+fn add_skips_partial_pages() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0800), 0x3000, MemoryKind::Usable);
+    regions.add(phys(0x9000_0000), 0x800, MemoryKind::Usable);
+
+    assert_eq!(sorted(&regions), [(0x8000_1000, 2)]);
+}
+
+#[test]
+fn remove_splits_region() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0000), 0x10000, MemoryKind::Usable);
+    regions.remove(phys(0x8000_4800), 0x1000);
+
+    assert_eq!(sorted(&regions), [(0x8000_0000, 4), (0x8000_6000, 10)]);
+}
+
+#[test]
+fn remove_trims_and_drops_regions() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0000), 0x4000, MemoryKind::Usable);
+    regions.add(phys(0x8001_0000), 0x4000, MemoryKind::Usable);
+    regions.add(phys(0x8002_0000), 0x4000, MemoryKind::Usable);
+    regions.remove(phys(0x8000_2000), 0x1_0000);
+    regions.remove(phys(0x8002_0000), 0x4000);
+
+    assert_eq!(sorted(&regions), [(0x8000_0000, 2), (0x8001_2000, 2)]);
+}
+
+#[test]
+fn remove_outside_regions() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0000), 0x4000, MemoryKind::Usable);
+    regions.remove(phys(0x7000_0000), 0x1000);
+    regions.remove(phys(0x8000_4000), 0);
+
+    assert_eq!(sorted(&regions), [(0x8000_0000, 4)]);
+}
+
+/// All regions, in list order.
+fn listed(regions: &MemoryRegions) -> Vec<(u64, usize, MemoryKind)> {
+    regions.iter()
+        .map(|region| (region.start.start_address().as_u64(), region.pages, region.kind))
+        .collect()
+}
+
+#[test]
+fn full_list_drops_regions() {
+    let mut regions = MemoryRegions::new();
+    for index in 0..(MAX_REGIONS as u64 + 1) {
+        regions.add(phys(index * 0x2000), 0x1000, MemoryKind::Usable);
+    }
+
+    assert_eq!(regions.len(), MAX_REGIONS);
+    assert_eq!(regions.dropped(), 1);
+}
+
+#[test]
+fn adjacent_ranges_coalesce() {
+    // firmware maps often list many small adjacent descriptors
+    let count = 3 * MAX_REGIONS as u64;
+    let regions = MemoryRegions::from_ranges((0..count).map(|index| {
+        let kind = if index < 2 * MAX_REGIONS as u64 { MemoryKind::Usable } else { MemoryKind::Firmware };
+        (phys(0x8000_0000 + index * 0x1000), 0x1000, kind)
+    }));
+
+    assert_eq!(regions.dropped(), 0);
+    assert_eq!(listed(&regions), [
+        (0x8000_0000, 2 * MAX_REGIONS, MemoryKind::Usable),
+        (0x8000_0000 + 2 * MAX_REGIONS as u64 * 0x1000, MAX_REGIONS, MemoryKind::Firmware),
+    ]);
+}
+
+#[test]
+fn full_list_is_normalized_to_make_room() {
+    // descending order, so regions only merge once the list is normalized
+    let count = 2 * MAX_REGIONS as u64;
+    let regions = MemoryRegions::from_ranges((0..count).rev().map(|index| {
+        (phys(0x8000_0000 + index * 0x1000), 0x1000, MemoryKind::Usable)
+    }));
+
+    assert_eq!(regions.dropped(), 0);
+    assert_eq!(listed(&regions), [(0x8000_0000, 2 * MAX_REGIONS, MemoryKind::Usable)]);
+}
+
+#[test]
+fn uefi_memory_kinds() {
+    assert_eq!(MemoryKind::from_uefi(EFI_CONVENTIONAL_MEMORY), MemoryKind::Usable);
+    assert_eq!(MemoryKind::from_uefi(EFI_LOADER_DATA), MemoryKind::Usable);
+    assert_eq!(MemoryKind::from_uefi(EFI_BOOT_SERVICES_CODE), MemoryKind::Usable);
+    assert_eq!(MemoryKind::from_uefi(EFI_BOOT_SERVICES_DATA), MemoryKind::Usable);
+    assert_eq!(MemoryKind::from_uefi(EFI_RUNTIME_SERVICES_CODE), MemoryKind::Firmware);
+    assert_eq!(MemoryKind::from_uefi(EFI_RUNTIME_SERVICES_DATA), MemoryKind::Firmware);
+    assert_eq!(MemoryKind::from_uefi(EFI_LOADER_CODE), MemoryKind::Reserved);
+    assert_eq!(MemoryKind::from_uefi(EFI_RESERVED_MEMORY_TYPE), MemoryKind::Reserved);
+    assert_eq!(MemoryKind::from_uefi(0x7000_0000), MemoryKind::Reserved);
+}
+
+#[test]
+fn add_rounds_reserved_outward() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0800), 0x1000, MemoryKind::Reserved);
+
+    assert_eq!(listed(&regions), [(0x8000_0000, 2, MemoryKind::Reserved)]);
+}
+
+#[test]
+fn normalize_sorts_and_merges() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_4000), 0x4000, MemoryKind::Usable);
+    regions.add(phys(0x9000_0000), 0x1000, MemoryKind::Usable);
+    regions.add(phys(0x8000_0000), 0x4000, MemoryKind::Usable);
+    regions.add(phys(0x8000_8000), 0x2000, MemoryKind::Firmware);
+    regions.add(phys(0x8000_A000), 0x2000, MemoryKind::Firmware);
+    regions.normalize();
+
+    assert_eq!(listed(&regions), [
+        (0x8000_0000, 8, MemoryKind::Usable),
+        (0x8000_8000, 4, MemoryKind::Firmware),
+        (0x9000_0000, 1, MemoryKind::Usable),
+    ]);
+}
+
+#[test]
+fn normalize_resolves_overlaps() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_2000), 0x2000, MemoryKind::Reserved);
+    regions.add(phys(0x8000_1000), 0x2000, MemoryKind::Firmware);
+    // overlaps both regions added before it
+    regions.add(phys(0x8000_0000), 0x8000, MemoryKind::Usable);
+    regions.add(phys(0x8000_6000), 0x4000, MemoryKind::Usable);
+    regions.normalize();
+
+    assert_eq!(listed(&regions), [
+        (0x8000_0000, 1, MemoryKind::Usable),
+        (0x8000_1000, 1, MemoryKind::Firmware),
+        (0x8000_2000, 2, MemoryKind::Reserved),
+        (0x8000_4000, 6, MemoryKind::Usable),
+    ]);
+
+    // normalizing again changes nothing
+    let normalized = listed(&regions);
+    regions.normalize();
+    assert_eq!(listed(&regions), normalized);
+}
+
+#[test]
+fn reserved_regions_cut_usable_memory() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0000), 0x8000, MemoryKind::Usable);
+    regions.add(phys(0x8000_2000), 0x1000, MemoryKind::Reserved);
+
+    // even before normalizing, no usable region covers the reserved one
+    assert_eq!(sorted(&regions), [(0x8000_0000, 2), (0x8000_3000, 5)]);
+}
+
+#[test]
+fn qemu_virt_memory() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let regions = MemoryRegions::from_fdt(&fdt);
+
+    assert_eq!(sorted(&regions), [(0x8000_0000, 0x8000)]);
+}
+
+#[test]
+fn qemu_virt_opensbi_reservations() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT_OPENSBI).unwrap();
+    let regions = MemoryRegions::from_fdt(&fdt);
+
+    // 0x8000_0000 - 0x8006_0000 reserved by OpenSBI,
+    // 0x8700_0000 - 0x8780_0000 reserved by /memreserve/
+    assert_eq!(listed(&regions), [
+        (0x8000_0000, 0x60, MemoryKind::Reserved),
+        (0x8006_0000, 0x6fa0, MemoryKind::Usable),
+        (0x8700_0000, 0x800, MemoryKind::Reserved),
+        (0x8780_0000, 0x800, MemoryKind::Usable),
+    ]);
+}
+
+#[test]
+fn hifive_unmatched_memory() {
+    let fdt = Fdt::from_buffer(HIFIVE_UNMATCHED).unwrap();
+    let regions = MemoryRegions::from_fdt(&fdt);
+
+    assert_eq!(sorted(&regions), [(0x8000_0000, 0x40_0000)]);
+}
+
+#[test]
+fn reserved_ranges_are_removed() {
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x8000_0000), 0x10_0000, MemoryKind::Usable);
+
+    let mut reserved = ReservedRanges::new();
+    reserved.add(Reservation::Kernel, phys(0x8002_0000), 0x2_0000);
+    reserved.add(Reservation::Stack, phys(0x800F_E800), 0x1000);
+    reserved.add(Reservation::UefiMemoryMap, phys(0x8010_0000), 0);
+    assert_eq!(reserved.len(), 2);
+
+    regions.add_reserved(&reserved);
+    assert_eq!(sorted(&regions), [(0x8000_0000, 0x20), (0x8004_0000, 0xBE)]);
+}
+
+#[test]
+fn touching_reservations_merge() {
+    let mut reserved = ReservedRanges::new();
+    reserved.add(Reservation::UefiMemoryMap, phys(0x8010_0000), 0x1000);
+    reserved.add(Reservation::UefiMemoryMap, phys(0x8010_1000), 0x800);
+    reserved.add(Reservation::UefiMemoryMap, phys(0x800F_F000), 0x1800);
+
+    // ranges of other reservations are kept apart
+    reserved.add(Reservation::Stack, phys(0x8010_1800), 0x800);
+
+    let ranges: Vec<_> = reserved.iter().map(|range| (range.start, range.end)).collect();
+    assert_eq!(ranges, [
+        (phys(0x800F_F000), phys(0x8010_1800)),
+        (phys(0x8010_1800), phys(0x8010_2000)),
+    ]);
+}
+
+#[test]
+fn full_reserved_list_widens_nearest_range() {
+    let mut reserved = ReservedRanges::new();
+    for index in 0..(MAX_RESERVED as u64 + 4) {
+        reserved.add(Reservation::Initrd, phys(0x8000_0000 + index * 0x10_0000), 0x1000);
+    }
+
+    // one more range in front of all others
+    reserved.add(Reservation::Stack, phys(0x7FF0_0000), 0x1000);
+    assert_eq!(reserved.len(), MAX_RESERVED);
+
+    let ranges: Vec<_> = reserved.iter().map(|range| (range.start, range.end)).collect();
+    assert_eq!(ranges[0], (phys(0x7FF0_0000), phys(0x8000_1000)));
+    assert_eq!(ranges[MAX_RESERVED - 1], (
+        phys(0x8000_0000 + (MAX_RESERVED as u64 - 1) * 0x10_0000),
+        phys(0x8000_0000 + (MAX_RESERVED as u64 + 3) * 0x10_0000 + 0x1000),
+    ));
+
+    // nothing reserved is ever handed out
+    let mut regions = MemoryRegions::new();
+    regions.add(phys(0x7FF0_0000), 0x2000_0000, MemoryKind::Usable);
+    regions.add_reserved(&reserved);
+    for index in 0..(MAX_RESERVED as u64 + 4) {
+        let address = phys(0x8000_0000 + index * 0x10_0000);
+        assert!(regions.usable().all(|region| address < region.start.start_address() || address >= region.end()));
+    }
+}
+
+#[test]
+fn fdt_blob_and_initrd_reserved() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT_OPENSBI).unwrap();
+    let mut reserved = ReservedRanges::new();
+    reserved.add_fdt(&fdt, phys(0x8720_0000));
+
+    let ranges: Vec<_> = reserved.iter().copied().collect();
+    assert_eq!(ranges, [
+        ReservedRange {
+            start: phys(0x8720_0000),
+            end: phys(0x8720_0000 + QEMU_VIRT_OPENSBI.len() as u64),
+            reservation: Reservation::DeviceTree,
+        },
+        ReservedRange {
+            start: phys(0x8600_0000),
+            end: phys(0x8610_0000),
+            reservation: Reservation::Initrd,
+        },
+    ]);
+
+    let mut regions = MemoryRegions::from_fdt(&fdt);
+    regions.add_reserved(&reserved);
+    assert_eq!(sorted(&regions), [(0x8006_0000, 0x5fa0), (0x8610_0000, 0xf00), (0x8780_0000, 0x800)]);
+}
+
+#[test]
+fn fdt_without_initrd() {
+    let fdt = Fdt::from_buffer(QEMU_VIRT).unwrap();
+    let mut reserved = ReservedRanges::new();
+    reserved.add_fdt(&fdt, phys(0x8200_0000));
+
+    assert_eq!(reserved.len(), 1);
+    assert_eq!(reserved.iter().next().unwrap().reservation, Reservation::DeviceTree);
+}
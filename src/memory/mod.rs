@@ -1,4 +1,36 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub mod address;
 pub mod frame;
+pub mod heap;
+pub mod layout;
+pub mod map;
+pub mod paging;
 pub mod register;
 
+#[cfg(test)]
+mod test_util;
+
+pub use address::{Frame, Page, PageSize, PhysAddr, Size1G, Size2M, Size4K, VirtAddr};
 pub use register::Register;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Offset of the direct map of physical memory, zero while physical memory
+/// is identity mapped.
+static DIRECT_MAP_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Virtual address through which the physical `address` is accessed.
+pub fn phys_to_virt(address: PhysAddr) -> VirtAddr {
+    VirtAddr::new(address.as_u64().wrapping_add(DIRECT_MAP_OFFSET.load(Ordering::Relaxed)))
+}
+
+/// Physical address of `address`, which must lie in the direct map.
+pub fn virt_to_phys(address: VirtAddr) -> PhysAddr {
+    PhysAddr::new(address.as_u64().wrapping_sub(DIRECT_MAP_OFFSET.load(Ordering::Relaxed)))
+}
+
+#[cfg(target_arch = "riscv64")]
+fn set_direct_map_offset(base: VirtAddr) {
+    DIRECT_MAP_OFFSET.store(base.as_u64(), Ordering::Relaxed);
+}
@@ -0,0 +1,385 @@
+//! Page table management for Sv39 and Sv48.
+//!
+//! Page tables are accessed through the direct map of physical memory.
+
+#[cfg(test)]
+#[path = "paging_tests.rs"]
+mod paging_tests;
+
+use bitflags::bitflags;
+
+use super::{Frame, PAGE_SIZE, Page, PageSize, PhysAddr, Size1G, VirtAddr, phys_to_virt};
+use super::frame::FrameAllocator;
+
+const ENTRY_COUNT: usize = 512;
+
+bitflags! {
+    pub struct PageTableFlags: u64 {
+        const VALID = 1 << 0;
+        const READ = 1 << 1;
+        const WRITE = 1 << 2;
+        const EXECUTE = 1 << 3;
+        const USER = 1 << 4;
+        const GLOBAL = 1 << 5;
+        const ACCESSED = 1 << 6;
+        const DIRTY = 1 << 7;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PagingMode {
+    Sv39,
+    Sv48,
+}
+
+impl PagingMode {
+    /// Number of page table levels.
+    pub fn levels(&self) -> usize {
+        match self {
+            PagingMode::Sv39 => 3,
+            PagingMode::Sv48 => 4,
+        }
+    }
+
+    /// Value of the satp MODE field.
+    fn satp_mode(&self) -> u64 {
+        match self {
+            PagingMode::Sv39 => 8,
+            PagingMode::Sv48 => 9,
+        }
+    }
+
+    /// Returns true if `address` is a valid virtual address, i.e. all bits
+    /// above the most significant translated bit are copies of it.
+    pub fn is_canonical(&self, address: VirtAddr) -> bool {
+        let bits = 12 + 9 * self.levels() as u32;
+        let upper = (address.as_u64() as i64) >> (bits - 1);
+
+        upper == 0 || upper == -1
+    }
+}
+
+/// Page table level holding leaf entries for pages of size `S`.
+fn leaf_level<S: PageSize>() -> usize {
+    ((S::SIZE / PAGE_SIZE).trailing_zeros() / 9) as usize
+}
+
+/// Size of the pages mapped by leaf entries on `level`.
+fn level_size(level: usize) -> u64 {
+    PAGE_SIZE << (9 * level)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapError {
+    /// The virtual address is not valid in the paging mode.
+    InvalidAddress(VirtAddr),
+    /// The addresses or size of a range are not page aligned.
+    Misaligned,
+    /// Leaf entries need at least one of READ or EXECUTE, and WRITE requires
+    /// READ.
+    InvalidFlags,
+    /// The page, or part of it, is already mapped.
+    AlreadyMapped(VirtAddr),
+    /// No frame was available for an intermediate page table.
+    OutOfMemory,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(transparent)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    pub const fn empty() -> Self {
+        PageTableEntry(0)
+    }
+
+    pub fn new(address: PhysAddr, flags: PageTableFlags) -> Self {
+        PageTableEntry((address.as_u64() >> 12) << 10 | flags.bits())
+    }
+
+    /// Physical address of the page or next level table.
+    pub fn address(&self) -> PhysAddr {
+        PhysAddr::new(((self.0 >> 10) & ((1 << 44) - 1)) << 12)
+    }
+
+    pub fn flags(&self) -> PageTableFlags {
+        PageTableFlags::from_bits_truncate(self.0)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.flags().contains(PageTableFlags::VALID)
+    }
+
+    /// Returns true if the entry maps a page, rather than pointing to the
+    /// next level table.
+    pub fn is_leaf(&self) -> bool {
+        self.flags().intersects(PageTableFlags::READ | PageTableFlags::WRITE | PageTableFlags::EXECUTE)
+    }
+}
+
+impl core::fmt::Debug for PageTableEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#018X} {:?}", self.address(), self.flags())
+    }
+}
+
+#[repr(C, align(4096))]
+pub struct PageTable {
+    pub entries: [PageTableEntry; ENTRY_COUNT],
+}
+
+/// Virtual address space, rooted at a single page table.
+///
+/// Page tables are allocated from a `FrameAllocator` as needed, and are
+/// never freed.
+pub struct AddressSpace {
+    mode: PagingMode,
+    root: Frame,
+}
+
+impl AddressSpace {
+    /// Create an empty address space, allocating the root table from `frames`.
+    pub fn new(mode: PagingMode, frames: &mut FrameAllocator) -> Option<Self> {
+        Some(AddressSpace {
+            mode,
+            root: allocate_table(frames)?,
+        })
+    }
+
+    pub fn mode(&self) -> PagingMode {
+        self.mode
+    }
+
+    /// Frame holding the root page table.
+    pub fn root(&self) -> Frame {
+        self.root
+    }
+
+    /// Map `page` to `frame`.
+    ///
+    /// Without hardware updating of the accessed and dirty bits (Svadu),
+    /// accessing the page faults unless `ACCESSED`, and for writes `DIRTY`,
+    /// are included in `flags`.
+    pub fn map<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        frame: Frame<S>,
+        flags: PageTableFlags,
+        frames: &mut FrameAllocator,
+    ) -> Result<(), MapError> {
+        self.map_level(page.start_address(), frame.start_address(), leaf_level::<S>(), flags, frames)
+    }
+
+    /// Map `size` bytes at `virt` to `phys`, using the largest pages allowed
+    /// by the alignment of the addresses.
+    ///
+    /// Mapping stops at the first error, leaving any pages mapped so far.
+    pub fn map_range(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        size: u64,
+        flags: PageTableFlags,
+        frames: &mut FrameAllocator,
+    ) -> Result<(), MapError> {
+        if !virt.is_aligned(PAGE_SIZE) || !phys.is_aligned(PAGE_SIZE) || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Misaligned);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let level = (0..=leaf_level::<Size1G>())
+                .rev()
+                .find(|level| {
+                    let bytes = level_size(*level);
+                    (virt + offset).is_aligned(bytes) && (phys + offset).is_aligned(bytes) &&
+                        size - offset >= bytes
+                })
+                .unwrap_or(0);
+
+            self.map_level(virt + offset, phys + offset, level, flags, frames)?;
+            offset += level_size(level);
+        }
+
+        Ok(())
+    }
+
+    /// Unmap `page`, returning the frame it was mapped to.
+    ///
+    /// Returns None unless the page is mapped by a leaf entry of its size.
+    /// Intermediate page tables are kept, even if left empty.
+    pub fn unmap<S: PageSize>(&mut self, page: Page<S>) -> Option<Frame<S>> {
+        let (entry, entry_level) = self.leaf_mut(page.start_address())?;
+        if entry_level != leaf_level::<S>() {
+            return None;
+        }
+
+        let frame = Frame::from_start_address(entry.address())?;
+
+        *entry = PageTableEntry::empty();
+        flush(page.start_address());
+
+        Some(frame)
+    }
+
+    /// Translate `virt` into a physical address.
+    pub fn translate(&self, virt: VirtAddr) -> Option<PhysAddr> {
+        let (entry, level) = self.leaf(virt)?;
+
+        Some(entry.address() + (virt.as_u64() & (level_size(level) - 1)))
+    }
+
+    /// Flags of the page containing `virt`.
+    pub fn flags(&self, virt: VirtAddr) -> Option<PageTableFlags> {
+        let (entry, _) = self.leaf(virt)?;
+
+        Some(entry.flags())
+    }
+
+    /// Value of satp selecting this address space, with ASID 0.
+    pub fn satp(&self) -> u64 {
+        self.mode.satp_mode() << 60 | self.root.start_address().as_u64() >> 12
+    }
+
+    /// Switch the current hart to this address space.
+    ///
+    /// # Safety
+    ///
+    /// The address space must map all code and data in use, including the
+    /// stack, at their current virtual addresses.
+    pub unsafe fn activate(&self) {
+        #[cfg(target_arch = "riscv64")]
+        asm!(
+            "csrw satp, {0}",
+            "sfence.vma",
+            in(reg) self.satp(),
+        );
+    }
+
+    /// Map a page of `level_size(level)` bytes at `virt` to `phys`, both of
+    /// which must be aligned to the page size.
+    fn map_level(
+        &mut self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        level: usize,
+        flags: PageTableFlags,
+        frames: &mut FrameAllocator,
+    ) -> Result<(), MapError> {
+        if !self.mode.is_canonical(virt) {
+            return Err(MapError::InvalidAddress(virt));
+        }
+
+        let permissions = flags & (PageTableFlags::READ | PageTableFlags::WRITE | PageTableFlags::EXECUTE);
+        if permissions.is_empty() || permissions == PageTableFlags::WRITE ||
+            permissions == PageTableFlags::WRITE | PageTableFlags::EXECUTE
+        {
+            return Err(MapError::InvalidFlags);
+        }
+
+        let mut table = self.root.start_address();
+        for table_level in ((level + 1)..self.mode.levels()).rev() {
+            let entry = entry_mut(table, virt, table_level);
+
+            if !entry.is_valid() {
+                let next_table = allocate_table(frames).ok_or(MapError::OutOfMemory)?;
+                *entry = PageTableEntry::new(next_table.start_address(), PageTableFlags::VALID);
+            } else if entry.is_leaf() {
+                return Err(MapError::AlreadyMapped(virt));
+            }
+
+            table = entry.address();
+        }
+
+        let entry = entry_mut(table, virt, level);
+        if entry.is_valid() {
+            return Err(MapError::AlreadyMapped(virt));
+        }
+
+        *entry = PageTableEntry::new(phys, flags | PageTableFlags::VALID);
+        flush(virt);
+
+        Ok(())
+    }
+
+    /// Copy of the leaf entry mapping `virt`, along with its level.
+    fn leaf(&self, virt: VirtAddr) -> Option<(PageTableEntry, usize)> {
+        let (table, level) = self.leaf_table(virt)?;
+
+        Some((entry(table, virt, level), level))
+    }
+
+    /// Find the leaf entry mapping `virt`, along with its level.
+    fn leaf_mut(&mut self, virt: VirtAddr) -> Option<(&mut PageTableEntry, usize)> {
+        let (table, level) = self.leaf_table(virt)?;
+
+        Some((entry_mut(table, virt, level), level))
+    }
+
+    /// Find the page table holding the leaf entry for `virt`, along with the
+    /// level of the entry.
+    fn leaf_table(&self, virt: VirtAddr) -> Option<(PhysAddr, usize)> {
+        if !self.mode.is_canonical(virt) {
+            return None;
+        }
+
+        let mut table = self.root.start_address();
+        for level in (0..self.mode.levels()).rev() {
+            let entry = entry(table, virt, level);
+
+            if !entry.is_valid() {
+                return None;
+            }
+
+            if entry.is_leaf() {
+                if level > leaf_level::<Size1G>() {
+                    return None;
+                }
+
+                return Some((table, level));
+            }
+
+            table = entry.address();
+        }
+
+        None
+    }
+}
+
+/// Allocate a zeroed page table.
+fn allocate_table(frames: &mut FrameAllocator) -> Option<Frame> {
+    let frame = frames.allocate(1)?;
+
+    // SAFETY: the frame was just handed out by the frame allocator
+    unsafe { phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>().write_bytes(0, 1) };
+
+    Some(frame)
+}
+
+/// Entry for `virt` on `level` of the page table at `table`.
+fn entry_mut<'a>(table: PhysAddr, virt: VirtAddr, level: usize) -> &'a mut PageTableEntry {
+    let index = (virt.as_u64() >> (12 + 9 * level)) as usize % ENTRY_COUNT;
+
+    // SAFETY: tables are only ever allocated by allocate_table, and owned by
+    // the address space
+    unsafe { &mut (*phys_to_virt(table).as_mut_ptr::<PageTable>()).entries[index] }
+}
+
+/// Copy of the entry for `virt` on `level` of the page table at `table`.
+fn entry(table: PhysAddr, virt: VirtAddr, level: usize) -> PageTableEntry {
+    let index = (virt.as_u64() >> (12 + 9 * level)) as usize % ENTRY_COUNT;
+
+    // SAFETY: as for entry_mut
+    unsafe { (*phys_to_virt(table).as_ptr::<PageTable>()).entries[index] }
+}
+
+/// Flush any cached translation of `virt` on the current hart.
+fn flush(virt: VirtAddr) {
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        asm!("sfence.vma {0}", in(reg) virt.as_u64());
+    }
+
+    #[cfg(not(target_arch = "riscv64"))]
+    let _ = virt;
+}
@@ -0,0 +1,198 @@
+use super::*;
+
+use crate::memory::{Size2M, Size4K};
+use crate::memory::test_util::{frame, new_frames, page, phys, virt};
+
+fn kernel_flags() -> PageTableFlags {
+    PageTableFlags::READ | PageTableFlags::WRITE |
+        PageTableFlags::ACCESSED | PageTableFlags::DIRTY
+}
+
+#[test]
+fn entry_encoding() {
+    let flags = PageTableFlags::VALID | PageTableFlags::READ | PageTableFlags::EXECUTE;
+    let entry = PageTableEntry::new(phys(0x8020_0000), flags);
+
+    assert_eq!(entry.0, 0x2008_0000 | 0b1011);
+    assert_eq!(entry.address(), phys(0x8020_0000));
+    assert_eq!(entry.flags(), flags);
+    assert!(entry.is_valid());
+    assert!(entry.is_leaf());
+
+    let table = PageTableEntry::new(phys(0x8020_0000), PageTableFlags::VALID);
+    assert!(table.is_valid());
+    assert!(!table.is_leaf());
+    assert!(!PageTableEntry::empty().is_valid());
+}
+
+#[test]
+fn canonical_addresses() {
+    assert!(PagingMode::Sv39.is_canonical(virt(0x0000_003F_FFFF_FFFF)));
+    assert!(PagingMode::Sv39.is_canonical(virt(0xFFFF_FFC0_0000_0000)));
+    assert!(!PagingMode::Sv39.is_canonical(virt(0x0000_0040_0000_0000)));
+    assert!(!PagingMode::Sv39.is_canonical(virt(0xFFFF_FF80_0000_0000)));
+
+    assert!(PagingMode::Sv48.is_canonical(virt(0x0000_0040_0000_0000)));
+    assert!(PagingMode::Sv48.is_canonical(virt(0xFFFF_8000_0000_0000)));
+    assert!(!PagingMode::Sv48.is_canonical(virt(0x0000_8000_0000_0000)));
+}
+
+#[test]
+fn map_and_translate() {
+    let mut frames = new_frames();
+    let free = frames.stats().free;
+
+    let mut space = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+    assert_eq!(frames.stats().free, free - 1);
+
+    space.map(page::<Size4K>(0x1000), frame(0x8000_3000), kernel_flags(), &mut frames).unwrap();
+
+    // one table for each of the two lower levels
+    assert_eq!(frames.stats().free, free - 3);
+    assert_eq!(space.translate(virt(0x1000)), Some(phys(0x8000_3000)));
+    assert_eq!(space.translate(virt(0x1ABC)), Some(phys(0x8000_3ABC)));
+    assert_eq!(space.translate(virt(0x2000)), None);
+    assert_eq!(space.translate(virt(0x0FFF)), None);
+
+    // lookups only need a shared reference
+    let shared = &space;
+    assert_eq!(shared.flags(virt(0x1000)), Some(kernel_flags() | PageTableFlags::VALID));
+    assert_eq!(shared.flags(virt(0x2000)), None);
+
+    // neighbouring pages share the tables
+    space.map(page::<Size4K>(0x2000), frame(0x8000_1000), kernel_flags(), &mut frames).unwrap();
+    assert_eq!(frames.stats().free, free - 3);
+    assert_eq!(space.translate(virt(0x2004)), Some(phys(0x8000_1004)));
+}
+
+#[test]
+fn huge_pages() {
+    let mut frames = new_frames();
+    let mut space = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+    let free = frames.stats().free;
+
+    space.map(page::<Size1G>(0x4000_0000), frame(0x8000_0000), kernel_flags(), &mut frames).unwrap();
+    assert_eq!(frames.stats().free, free);
+    assert_eq!(space.translate(virt(0x5234_5678)), Some(phys(0x9234_5678)));
+
+    space.map(page::<Size2M>(0x20_0000), frame(0xC020_0000), kernel_flags(), &mut frames).unwrap();
+    assert_eq!(frames.stats().free, free - 1);
+    assert_eq!(space.translate(virt(0x3F_FFFF)), Some(phys(0xC03F_FFFF)));
+
+    // pages are only unmapped at the size they were mapped with
+    assert_eq!(space.unmap(page::<Size4K>(0x4000_1000)), None);
+    assert_eq!(space.unmap(page::<Size1G>(0x4000_0000)), Some(frame(0x8000_0000)));
+    assert_eq!(space.translate(virt(0x4000_0000)), None);
+    assert_eq!(space.unmap(page::<Size2M>(0x20_0000)), Some(frame(0xC020_0000)));
+    assert_eq!(space.unmap(page::<Size2M>(0x20_0000)), None);
+}
+
+#[test]
+fn higher_half() {
+    let mut frames = new_frames();
+
+    let mut sv39 = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+    sv39.map(page::<Size1G>(0xFFFF_FFC0_0000_0000), frame(0x8000_0000), kernel_flags(), &mut frames)
+        .unwrap();
+    assert_eq!(sv39.translate(virt(0xFFFF_FFC0_0000_1234)), Some(phys(0x8000_1234)));
+    assert_eq!(sv39.translate(virt(0x0000_0000_0000_1234)), None);
+
+    let mut sv48 = AddressSpace::new(PagingMode::Sv48, &mut frames).unwrap();
+    sv48.map(page::<Size4K>(0xFFFF_8000_0000_0000), frame(0x8000_0000), kernel_flags(), &mut frames)
+        .unwrap();
+    assert_eq!(sv48.translate(virt(0xFFFF_8000_0000_0123)), Some(phys(0x8000_0123)));
+}
+
+#[test]
+fn map_errors() {
+    let mut frames = new_frames();
+    let mut space = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+
+    assert_eq!(
+        space.map(page::<Size4K>(0x0000_0040_0000_0000), frame(0), kernel_flags(), &mut frames),
+        Err(MapError::InvalidAddress(virt(0x0000_0040_0000_0000)))
+    );
+    assert_eq!(
+        space.map(page::<Size4K>(0x1000), frame(0x1000), PageTableFlags::WRITE, &mut frames),
+        Err(MapError::InvalidFlags)
+    );
+    assert_eq!(
+        space.map(page::<Size4K>(0x1000), frame(0x1000), PageTableFlags::GLOBAL, &mut frames),
+        Err(MapError::InvalidFlags)
+    );
+
+    // a page inside an existing huge page
+    space.map(page::<Size2M>(0x20_0000), frame(0x8000_0000), kernel_flags(), &mut frames).unwrap();
+    assert_eq!(
+        space.map(page::<Size4K>(0x20_1000), frame(0x1000), kernel_flags(), &mut frames),
+        Err(MapError::AlreadyMapped(virt(0x20_1000)))
+    );
+
+    // a huge page over existing smaller pages
+    space.map(page::<Size4K>(0x1000), frame(0x1000), kernel_flags(), &mut frames).unwrap();
+    assert_eq!(
+        space.map(page::<Size2M>(0), frame(0), kernel_flags(), &mut frames),
+        Err(MapError::AlreadyMapped(virt(0)))
+    );
+    assert_eq!(
+        space.map(page::<Size4K>(0x1000), frame(0x2000), kernel_flags(), &mut frames),
+        Err(MapError::AlreadyMapped(virt(0x1000)))
+    );
+}
+
+#[test]
+fn table_allocation_failure() {
+    let mut frames = new_frames();
+    let mut space = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+
+    while frames.allocate(1).is_some() {}
+
+    assert_eq!(
+        space.map(page::<Size4K>(0x1000), frame(0x1000), kernel_flags(), &mut frames),
+        Err(MapError::OutOfMemory)
+    );
+    assert_eq!(space.translate(virt(0x1000)), None);
+}
+
+#[test]
+fn map_range_uses_largest_pages() {
+    let mut frames = new_frames();
+    let mut space = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+    let free = frames.stats().free;
+
+    // 4 KiB up to the first 2 MiB boundary, then 2 MiB up to 1 GiB, a single
+    // 1 GiB page, and a trailing 4 KiB page
+    let start = 0x4000_0000 - 0x20_0000 - 0x1000;
+    let size = 0x1000 + 0x20_0000 + 0x4000_0000 + 0x1000;
+    space.map_range(virt(start), phys(start), size, kernel_flags(), &mut frames).unwrap();
+
+    assert_eq!(space.unmap(page::<Size4K>(start)), Some(frame(start)));
+    assert_eq!(space.unmap(page::<Size2M>(0x3FE0_0000)), Some(frame(0x3FE0_0000)));
+    assert_eq!(space.unmap(page::<Size1G>(0x4000_0000)), Some(frame(0x4000_0000)));
+    assert_eq!(space.unmap(page::<Size4K>(0x8000_0000)), Some(frame(0x8000_0000)));
+    assert_eq!(space.translate(virt(0x8000_1000)), None);
+
+    // level 1 and level 0 tables for both 4 KiB pages, sharing the level 1
+    // table with the 2 MiB page
+    assert_eq!(frames.stats().free, free - 4);
+
+    assert_eq!(
+        space.map_range(virt(0x1234), phys(0), 0x1000, kernel_flags(), &mut frames),
+        Err(MapError::Misaligned)
+    );
+    assert_eq!(
+        space.map_range(virt(0x1000), phys(0x800), 0x1000, kernel_flags(), &mut frames),
+        Err(MapError::Misaligned)
+    );
+}
+
+#[test]
+fn satp_value() {
+    let mut frames = new_frames();
+
+    let sv39 = AddressSpace::new(PagingMode::Sv39, &mut frames).unwrap();
+    assert_eq!(sv39.satp(), 8 << 60 | sv39.root().start_address().as_u64() >> 12);
+
+    let sv48 = AddressSpace::new(PagingMode::Sv48, &mut frames).unwrap();
+    assert_eq!(sv48.satp(), 9 << 60 | sv48.root().start_address().as_u64() >> 12);
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the memory management tests, which run on the host.
+
+use super::{Frame, Page, PageSize, PhysAddr, VirtAddr, PAGE_SIZE};
+use super::frame::{Buddy, FrameAllocator};
+
+pub fn phys(address: u64) -> PhysAddr {
+    PhysAddr::new(address)
+}
+
+pub fn virt(address: u64) -> VirtAddr {
+    VirtAddr::new(address)
+}
+
+pub fn frame<S: PageSize>(address: u64) -> Frame<S> {
+    Frame::from_start_address(phys(address)).unwrap()
+}
+
+pub fn page<S: PageSize>(address: u64) -> Page<S> {
+    Page::from_start_address(virt(address)).unwrap()
+}
+
+/// Allocate `maps` map windows worth of memory on the host heap, aligned to
+/// the window size.
+pub fn new_region(maps: usize) -> u64 {
+    let map_size = Buddy::PAGE_COUNT * PAGE_SIZE as usize;
+    let layout = std::alloc::Layout::from_size_align(map_size * maps, map_size).unwrap();
+
+    unsafe { std::alloc::alloc(layout) as u64 }
+}
+
+/// Frame allocator over a single map worth of memory on the host heap.
+pub fn new_frames() -> FrameAllocator {
+    let mut frames = FrameAllocator::new();
+    unsafe { frames.add_region(frame(new_region(1)), Buddy::PAGE_COUNT) };
+
+    frames
+}
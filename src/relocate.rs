@@ -0,0 +1,100 @@
+//! Self-relocation of the kernel image.
+//!
+//! The kernel is linked as a position independent shared object, with the
+//! dynamic relocations needed to run at any address left in `.rela.dyn`.
+//! Only `R_RISCV_RELATIVE` relocations are expected, as the image neither
+//! imports nor exports any symbols.
+//!
+//! Relocation runs before anything else, so none of the code here may touch
+//! statics, or panic.
+
+#[cfg(test)]
+#[path = "relocate_tests.rs"]
+mod relocate_tests;
+
+pub const R_RISCV_NONE: u32 = 0;
+pub const R_RISCV_RELATIVE: u32 = 3;
+
+/// ELF64 relocation entry with addend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Rela {
+    pub offset: u64,
+    pub info: u64,
+    pub addend: i64,
+}
+
+impl Rela {
+    /// Relocation type, e.g. `R_RISCV_RELATIVE`.
+    pub fn kind(&self) -> u32 {
+        self.info as u32
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.kind() == R_RISCV_NONE || self.kind() == R_RISCV_RELATIVE
+    }
+}
+
+/// Apply `relocations` to an image loaded at `base`.
+///
+/// Unsupported relocations are skipped, and counted in the return value.
+///
+/// # Safety
+///
+/// `relocations` must belong to the image at `base`, and the locations they
+/// refer to must be writable.
+pub unsafe fn apply(relocations: &[Rela], base: u64) -> usize {
+    let mut unsupported = 0;
+
+    for relocation in relocations {
+        match relocation.kind() {
+            R_RISCV_NONE => {},
+            R_RISCV_RELATIVE => {
+                let location = base.wrapping_add(relocation.offset) as *mut u64;
+                location.write(base.wrapping_add(relocation.addend as u64));
+            },
+            _ => unsupported += 1,
+        }
+    }
+
+    unsupported
+}
+
+/// Relocation entries of the running kernel image, along with the address it
+/// is running at.
+#[cfg(target_arch = "riscv64")]
+pub fn image_relocations() -> (u64, &'static [Rela]) {
+    let (base, start, end): (u64, u64, u64);
+
+    // SAFETY: only computes addresses
+    unsafe {
+        asm!(
+            "lla {0}, __kernel_start",
+            "lla {1}, __rela_dyn_start",
+            "lla {2}, __rela_dyn_end",
+            out(reg) base,
+            out(reg) start,
+            out(reg) end,
+        );
+    }
+
+    let count = (end - start) as usize / core::mem::size_of::<Rela>();
+
+    // SAFETY: the linker script places the symbols around .rela.dyn
+    (base, unsafe { core::slice::from_raw_parts(start as *const Rela, count) })
+}
+
+/// Relocate the running kernel image for the address it is running at,
+/// returning the number of unsupported relocations.
+///
+/// # Safety
+///
+/// Must be called before any statics holding pointers are used, and again
+/// whenever the image starts running at a different address. The data
+/// sections of the image must be writable.
+#[cfg(target_arch = "riscv64")]
+pub unsafe fn relocate_self() -> usize {
+    let (base, relocations) = image_relocations();
+
+    apply(relocations, base)
+}
@@ -0,0 +1,56 @@
+use super::*;
+
+fn rela(offset: u64, kind: u32, addend: i64) -> Rela {
+    Rela {
+        offset,
+        info: kind as u64,
+        addend,
+    }
+}
+
+#[test]
+fn relocation_kinds() {
+    assert_eq!(rela(0, R_RISCV_RELATIVE, 0).kind(), R_RISCV_RELATIVE);
+    assert!(rela(0, R_RISCV_RELATIVE, 0).is_supported());
+    assert!(rela(0, R_RISCV_NONE, 0).is_supported());
+
+    // symbol index in the upper half of info
+    let absolute = Rela { offset: 0, info: 5 << 32 | 2, addend: 0 };
+    assert_eq!(absolute.kind(), 2);
+    assert!(!absolute.is_supported());
+}
+
+#[test]
+fn apply_relative() {
+    let mut image = vec![0u64; 8];
+    let base = image.as_mut_ptr() as u64;
+
+    let relocations = [
+        rela(8, R_RISCV_RELATIVE, 0x40),
+        rela(24, R_RISCV_RELATIVE, 0),
+        rela(32, R_RISCV_NONE, 0x1234),
+    ];
+
+    assert_eq!(unsafe { apply(&relocations, base) }, 0);
+    assert_eq!(image, vec![0, base + 0x40, 0, base, 0, 0, 0, 0]);
+
+    // relocating again is harmless
+    image[1] = 0xDEAD;
+    assert_eq!(unsafe { apply(&relocations, base) }, 0);
+    assert_eq!(image, vec![0, base + 0x40, 0, base, 0, 0, 0, 0]);
+}
+
+#[test]
+fn apply_skips_unsupported() {
+    let mut image = vec![0u64; 4];
+    let base = image.as_mut_ptr() as u64;
+
+    let relocations = [
+        rela(0, 2, 0x10),
+        rela(8, R_RISCV_RELATIVE, 0x10),
+        rela(16, 5, 0),
+    ];
+
+    assert_eq!(unsafe { apply(&relocations, base) }, 2);
+    assert_eq!(image, vec![0, base + 0x10, 0, 0]);
+}
@@ -1,31 +1,82 @@
-use lazy_static::lazy_static;
 use spin::Mutex;
 
-use crate::drivers::uart::{self, Uart};
+use crate::drivers::{self, Device, uart::{Uart, UartConfig}};
+use crate::fdt::{Fdt, Node};
 
 type UartDevice = dyn Uart + Send + 'static;
 
-lazy_static! {
-    pub static ref WRITER: Mutex<&'static mut UartDevice> = Mutex::new({
-        #[cfg(feature = "fu740")]
-        let uart = unsafe {
-            uart::fu740_c000::UartFu740::new(0).unwrap()
-        };
+/// Kernel serial console.
+///
+/// Output is discarded until a UART device has been brought up by `init`.
+pub static WRITER: Mutex<SerialWriter> = Mutex::new(SerialWriter { uart: None });
 
-        #[cfg(feature = "qemu")]
-        let uart = unsafe {
-            uart::ns16550a::UartNs16550a::new(0x1000_0000 as *const core::ffi::c_void)
-        };
+pub struct SerialWriter {
+    uart: Option<&'static mut UartDevice>,
+}
 
-        uart.init(uart::StopBits::OneStopBit);
+impl SerialWriter {
+    fn attach(&mut self, uart: &'static mut UartDevice, node: &Node<'_, '_>, mut config: UartConfig) {
+        config.clock_frequency = node.property("clock-frequency")
+            .and_then(|property| property.as_u32());
 
-        uart
-    });
+        uart.init(&config);
+        self.uart = Some(uart);
+    }
+
+    /// Send a byte over the console UART.
+    pub fn send(&mut self, data: u8) {
+        if let Some(ref mut uart) = self.uart {
+            uart.send(data);
+        }
+    }
+
+    /// Send a string over the console UART.
+    pub fn write(&mut self, string: &str) {
+        if let Some(ref mut uart) = self.uart {
+            uart.write(string);
+        }
+    }
 }
 
-impl core::fmt::Write for &mut UartDevice {
+impl core::fmt::Write for SerialWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        Uart::write(*self, s);
+        self.write(s);
         Ok(())
     }
 }
+
+/// Bring up the serial console.
+///
+/// The console is selected by the `stdout-path` of the `/chosen` node,
+/// falling back to the first UART found in the device tree.
+///
+/// Any UART brought up earlier is dropped first, as its registers may no
+/// longer be mapped at the same address.
+///
+/// Returns false if no suitable UART was found.
+pub fn init(fdt: &Fdt<'_>) -> bool {
+    let mut writer = WRITER.lock();
+    writer.uart = None;
+
+    if let Some((node, options)) = fdt.stdout() {
+        let config = match options {
+            Some(options) => UartConfig::parse(options).unwrap_or_default(),
+            None => UartConfig::default(),
+        };
+
+        if let Some(Device::Uart(uart)) = drivers::probe(&node) {
+            writer.attach(uart, &node, config);
+            return true;
+        }
+    }
+
+    drivers::probe_all(fdt, |node, device| match device {
+        Device::Uart(uart) => {
+            if writer.uart.is_none() {
+                writer.attach(uart, node, UartConfig::default());
+            }
+        },
+    });
+
+    writer.uart.is_some()
+}
@@ -0,0 +1,88 @@
+//! Synchronization primitives.
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+
+use spin::{Mutex, MutexGuard};
+
+/// Spinlock which also masks supervisor interrupts on the current hart while
+/// held.
+///
+/// Unlike a plain `spin::Mutex`, this can be shared with interrupt handlers
+/// without risking a deadlock on the hart holding the lock.
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        IrqMutex { inner: Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let interrupts_enabled = disable_interrupts();
+
+        IrqMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            interrupts_enabled,
+        }
+    }
+}
+
+pub struct IrqMutexGuard<'a, T> {
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // release the lock before an interrupt handler gets a chance to run
+        // SAFETY: the guard is never used again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+
+        if self.interrupts_enabled {
+            enable_interrupts();
+        }
+    }
+}
+
+/// Clear sstatus.SIE, returning whether it was set.
+#[cfg(target_arch = "riscv64")]
+fn disable_interrupts() -> bool {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrrci {0}, sstatus, 2", out(reg) sstatus);
+    }
+
+    sstatus & 2 != 0
+}
+
+#[cfg(target_arch = "riscv64")]
+fn enable_interrupts() {
+    unsafe {
+        asm!("csrsi sstatus, 2");
+    }
+}
+
+// No interrupts to mask when built for other architectures, i.e. host tests.
+#[cfg(not(target_arch = "riscv64"))]
+fn disable_interrupts() -> bool {
+    false
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+fn enable_interrupts() {}